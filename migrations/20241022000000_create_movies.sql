@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS movies (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    year INTEGER NOT NULL,
+    was_good INTEGER NOT NULL
+);
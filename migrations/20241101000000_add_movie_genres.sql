@@ -0,0 +1,2 @@
+-- JSON array of genre names
+ALTER TABLE movies ADD COLUMN genres TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1 @@
+ALTER TABLE movies ADD COLUMN deleted_at INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE movies ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
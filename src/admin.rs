@@ -0,0 +1,86 @@
+use crate::audit::{AuditEntry, AUDIT_BUFFER_CAPACITY};
+use crate::extract::Json;
+use crate::{auth, AppState};
+use axum::extract::{Query, State};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+#[derive(Serialize, Debug)]
+pub struct CacheContents {
+    size: usize,
+    keys: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheFlushed {
+    cleared: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InvalidateTag {
+    tag: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagInvalidated {
+    invalidated: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuditParams {
+    limit: Option<usize>,
+}
+
+async fn cache_contents(State(state): State<AppState>) -> Json<CacheContents> {
+    let keys = state.cache.keys().await;
+    Json(CacheContents {
+        size: keys.len(),
+        keys,
+    })
+}
+
+async fn flush_cache(State(state): State<AppState>) -> Json<CacheFlushed> {
+    let cleared = state.cache.clear().await;
+    tracing::info!("flushed {cleared} cached movies");
+    Json(CacheFlushed { cleared })
+}
+
+// Tags are genres, e.g. after reimporting everything in one
+async fn invalidate_tag(
+    State(state): State<AppState>,
+    Json(body): Json<InvalidateTag>,
+) -> Json<TagInvalidated> {
+    let invalidated = state.invalidate_tag(&body.tag).await;
+    tracing::info!(
+        "invalidated {invalidated} cached movies tagged {:?}",
+        body.tag
+    );
+    Json(TagInvalidated { invalidated })
+}
+
+// Only what's still buffered, older entries are in the audit log file if one is configured
+async fn audit_entries(
+    State(state): State<AppState>,
+    Query(params): Query<AuditParams>,
+) -> Json<Vec<AuditEntry>> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .min(AUDIT_BUFFER_CAPACITY);
+    Json(state.audit.recent(limit).await)
+}
+
+/// Debugging endpoints, always behind the API key regardless of `API_KEY_PROTECT_READS`
+pub fn router(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/admin/cache", get(cache_contents).delete(flush_cache))
+        .route("/admin/cache/invalidate", post(invalidate_tag))
+        .route("/admin/audit", get(audit_entries))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ))
+}
@@ -0,0 +1,160 @@
+use crate::auth::API_KEY_HEADER;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::io;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Entries kept in memory for `GET /admin/audit`, the file (when configured) keeps everything
+pub const AUDIT_BUFFER_CAPACITY: usize = 1000;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    // Unix seconds
+    pub timestamp: u64,
+    pub action: AuditAction,
+    pub movie_id: String,
+    pub actor: String,
+}
+
+// FNV-1a, stable across instances and releases unlike std's hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Who made a request, a fingerprint of their API key or `anonymous`. Never the key itself.
+#[derive(Debug, Clone)]
+pub struct Actor(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let actor = match parts.headers.get(&API_KEY_HEADER) {
+            Some(key) => format!("key:{:08x}", fnv1a(key.as_bytes()) >> 32),
+            None => "anonymous".to_string(),
+        };
+        Ok(Actor(actor))
+    }
+}
+
+// tokio's File only hands writes to a background task, so each entry is flushed and synced
+// before the mutation it records is reported as done
+async fn append(file: &mut File, line: &[u8]) -> io::Result<()> {
+    file.write_all(line).await?;
+    file.flush().await?;
+    file.sync_data().await
+}
+
+/// Append-only record of every mutation, a ring buffer of the latest entries plus an optional
+/// JSON lines file
+#[derive(Default)]
+pub struct AuditLog {
+    recent: Mutex<VecDeque<AuditEntry>>,
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    /// Appends to `path`, creating it if needed
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(AuditLog {
+            recent: Mutex::default(),
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    pub async fn record(&self, action: AuditAction, movie_id: &str, actor: &Actor) {
+        let entry = AuditEntry {
+            timestamp: crate::unix_now(),
+            action,
+            movie_id: movie_id.to_string(),
+            actor: actor.0.clone(),
+        };
+
+        // The file lock is held until the entry is buffered too, so both agree on the order
+        let mut locked_file = match &self.file {
+            Some(file) => Some(file.lock().await),
+            None => None,
+        };
+        if let Some(file) = locked_file.as_mut() {
+            let mut line = serde_json::to_vec(&entry).unwrap_or_default();
+            line.push(b'\n');
+            if let Err(err) = append(file, &line).await {
+                tracing::error!("failed to write audit entry {entry:?}: {err}");
+            }
+        }
+
+        let mut locked_recent = self.recent.lock().await;
+        if locked_recent.len() == AUDIT_BUFFER_CAPACITY {
+            locked_recent.pop_front();
+        }
+        locked_recent.push_back(entry);
+    }
+
+    /// Makes sure every recorded entry is on disk, called once on shutdown
+    pub async fn flush(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut locked_file = file.lock().await;
+        locked_file.flush().await?;
+        locked_file.sync_data().await
+    }
+
+    /// The newest `limit` entries, oldest first
+    pub async fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let locked_recent = self.recent.lock().await;
+        let skip = locked_recent.len().saturating_sub(limit);
+        locked_recent.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_are_on_disk_once_recorded() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let log = AuditLog::open(&path).await.unwrap();
+        let actor = Actor("anonymous".to_string());
+        log.record(AuditAction::Create, "heat", &actor).await;
+        log.record(AuditAction::Delete, "heat", &actor).await;
+
+        // Read without going through the log's handle or waiting for shutdown
+        let written = tokio::fs::read_to_string(&path).await.unwrap();
+        let actions: Vec<String> = written
+            .lines()
+            .map(|line| {
+                let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+                entry["action"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(actions, ["create", "delete"]);
+        log.flush().await.unwrap();
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
@@ -0,0 +1,34 @@
+use crate::error::ApiError;
+use crate::AppState;
+use axum::extract::{Request, State};
+use axum::http::HeaderName;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Rejects requests without a matching `X-Api-Key` header. Does nothing when no `API_KEY` is
+/// configured (dev mode).
+pub async fn require_api_key(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(expected) = &state.api_key else {
+        return next.run(req).await;
+    };
+
+    let provided = req
+        .headers()
+        .get(&API_KEY_HEADER)
+        .map(|value| value.as_bytes());
+    match provided {
+        Some(provided) if constant_time_eq(provided, expected.as_bytes()) => next.run(req).await,
+        Some(_) => ApiError::Unauthorized("invalid API key").into_response(),
+        None => ApiError::Unauthorized("missing X-Api-Key header").into_response(),
+    }
+}
+
+// Don't leak how much of the key matched through response timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
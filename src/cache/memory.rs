@@ -0,0 +1,142 @@
+use super::Cache;
+use crate::Movie;
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+#[derive(Debug, Clone)]
+struct CachedMovie {
+    pub movie: Movie,
+    pub inserted_at: Instant,
+}
+
+impl CachedMovie {
+    fn new(movie: Movie) -> Self {
+        CachedMovie {
+            movie,
+            inserted_at: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.inserted_at.elapsed() >= ttl
+    }
+}
+
+/// Per-process cache, bounded with least recently used entries evicted once it's full
+pub struct MemoryCache {
+    entries: RwLock<LruCache<String, CachedMovie>>,
+    capacity: NonZeroUsize,
+    // Cached entries older than this are treated as a miss
+    ttl: Duration,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        MemoryCache {
+            entries: RwLock::new(LruCache::new(capacity)),
+            capacity,
+            ttl,
+        }
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, id: &str) -> Option<Movie> {
+        // A hit bumps recency so this needs write access
+        let mut locked_entries = self.entries.write().await;
+        locked_entries
+            .get(id)
+            .filter(|cached| !cached.is_expired(self.ttl))
+            .map(|cached| cached.movie.clone())
+    }
+
+    async fn set(&self, movie: Movie) {
+        let mut locked_entries = self.entries.write().await;
+        // This also overwrites an expired entry in place
+        locked_entries.put(movie.id.clone(), CachedMovie::new(movie));
+    }
+
+    async fn invalidate(&self, id: &str) {
+        let mut locked_entries = self.entries.write().await;
+        locked_entries.pop(id);
+    }
+
+    // Most recently used first, expired entries that haven't been evicted yet are included
+    async fn keys(&self) -> Vec<String> {
+        let locked_entries = self.entries.read().await;
+        locked_entries.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    async fn clear(&self) -> usize {
+        let mut locked_entries = self.entries.write().await;
+        let cleared = locked_entries.len();
+        locked_entries.clear();
+        cleared
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.capacity.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie(id: &str) -> Movie {
+        Movie {
+            id: id.to_string(),
+            name: "Heat".to_string(),
+            year: 1995,
+            was_good: true,
+            genres: Vec::new(),
+            rating: None,
+            deleted_at: None,
+            version: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_are_ignored_once_expired() {
+        let cache = MemoryCache::new(NonZeroUsize::new(10).unwrap(), Duration::from_millis(20));
+        cache.set(movie("heat")).await;
+        assert!(cache.get("heat").await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(cache.get("heat").await.is_none());
+
+        // Setting it again overwrites the expired entry
+        cache.set(movie("heat")).await;
+        assert!(cache.get("heat").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted_when_full() {
+        let cache = MemoryCache::new(NonZeroUsize::new(3).unwrap(), Duration::from_secs(60));
+        for id in ["a", "b", "c"] {
+            cache.set(movie(id)).await;
+        }
+        // A hit makes `a` the most recent, so `b` is now the oldest
+        assert!(cache.get("a").await.is_some());
+
+        cache.set(movie("d")).await;
+        assert!(cache.get("b").await.is_none());
+        for id in ["a", "c", "d"] {
+            assert!(cache.get(id).await.is_some(), "{id} was evicted");
+        }
+    }
+
+    #[tokio::test]
+    async fn first_entry_is_evicted_after_capacity_plus_one_inserts() {
+        let cache = MemoryCache::new(NonZeroUsize::new(3).unwrap(), Duration::from_secs(60));
+        for id in ["a", "b", "c", "d"] {
+            cache.set(movie(id)).await;
+        }
+        assert!(cache.get("a").await.is_none());
+        assert_eq!(cache.keys().await.len(), 3);
+    }
+}
@@ -0,0 +1,33 @@
+mod memory;
+mod redis;
+mod tags;
+
+pub use self::memory::MemoryCache;
+pub use self::redis::RedisCache;
+pub use self::tags::CacheTags;
+
+use crate::Movie;
+use async_trait::async_trait;
+
+/// Read-through cache in front of the store. Backend failures are logged and behave like misses,
+/// the store stays the source of truth.
+#[async_trait]
+pub trait Cache: Send + Sync {
+    /// `None` on a miss, expired entries count as one
+    async fn get(&self, id: &str) -> Option<Movie>;
+
+    async fn set(&self, movie: Movie);
+
+    async fn invalidate(&self, id: &str);
+
+    /// Every cached id, for debugging
+    async fn keys(&self) -> Vec<String>;
+
+    /// Drops every entry, returning how many there were
+    async fn clear(&self) -> usize;
+
+    /// Most entries that fit, `None` if the backend handles eviction itself
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+}
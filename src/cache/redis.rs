@@ -0,0 +1,126 @@
+use super::Cache;
+use crate::Movie;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisResult};
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+// Keeps our entries apart from anything else sharing the Redis instance
+const KEY_PREFIX: &str = "movie:";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn key(id: &str) -> String {
+    format!("{KEY_PREFIX}{id}")
+}
+
+/// Shared by every instance, so an invalidation on one node is seen by all of them. Entries
+/// expire through Redis' own TTL.
+pub struct RedisCache {
+    // Reconnects on its own, cheap to clone for each command
+    conn: ConnectionManager,
+    ttl: Duration,
+}
+
+impl RedisCache {
+    /// Fails if Redis can't be reached right now, so the caller can fall back to another cache
+    pub async fn connect(url: &str, ttl: Duration) -> RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = tokio::time::timeout(CONNECT_TIMEOUT, client.get_connection_manager())
+            .await
+            .map_err(|_| {
+                redis::RedisError::from((redis::ErrorKind::Io, "timed out connecting"))
+            })??;
+        Ok(RedisCache { conn, ttl })
+    }
+
+    async fn scan_keys(&self) -> RedisResult<Vec<String>> {
+        let mut conn = self.conn.clone();
+        let mut iter = conn
+            .scan_match::<_, String>(format!("{KEY_PREFIX}*"))
+            .await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next().await {
+            keys.push(key?);
+        }
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, id: &str) -> Option<Movie> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = match conn.get(key(id)).await {
+            Ok(value) => value,
+            Err(err) => {
+                tracing::warn!("redis GET failed: {err}");
+                return None;
+            }
+        };
+        // Entries written by an older version may not parse, treat those as a miss too
+        value.and_then(|json| serde_json::from_str(&json).ok())
+    }
+
+    async fn set(&self, movie: Movie) {
+        let json = match serde_json::to_string(&movie) {
+            Ok(json) => json,
+            Err(err) => {
+                tracing::error!("failed to serialize movie for redis: {err}");
+                return;
+            }
+        };
+        let mut conn = self.conn.clone();
+        // SETEX rejects 0, round sub-second TTLs up
+        let ttl_secs = self.ttl.as_secs().max(1);
+        if let Err(err) = conn
+            .set_ex::<_, _, ()>(key(&movie.id), json, ttl_secs)
+            .await
+        {
+            tracing::warn!("redis SETEX failed: {err}");
+        }
+    }
+
+    async fn invalidate(&self, id: &str) {
+        let mut conn = self.conn.clone();
+        // Other nodes keep serving the old movie until it expires if this fails
+        if let Err(err) = conn.del::<_, ()>(key(id)).await {
+            tracing::error!("redis DEL failed, `{id}` may be stale until it expires: {err}");
+        }
+    }
+
+    async fn keys(&self) -> Vec<String> {
+        match self.scan_keys().await {
+            Ok(keys) => keys
+                .into_iter()
+                .filter_map(|key| key.strip_prefix(KEY_PREFIX).map(str::to_string))
+                .collect(),
+            Err(err) => {
+                tracing::warn!("redis SCAN failed: {err}");
+                Vec::new()
+            }
+        }
+    }
+
+    async fn clear(&self) -> usize {
+        let keys = match self.scan_keys().await {
+            Ok(keys) => keys,
+            Err(err) => {
+                tracing::warn!("redis SCAN failed: {err}");
+                return 0;
+            }
+        };
+        if keys.is_empty() {
+            return 0;
+        }
+        let mut conn = self.conn.clone();
+        match conn.del::<_, usize>(&keys).await {
+            Ok(cleared) => cleared,
+            Err(err) => {
+                tracing::error!("redis DEL failed: {err}");
+                0
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::collections::{HashMap, HashSet};
+use tokio::sync::Mutex;
+
+/// Which cached ids carry each tag, kept next to the cache rather than in a backend so it works
+/// the same for all of them. Entries are only dropped when their tag is invalidated, so an id
+/// can outlive its cache entry here; invalidating it again is harmless.
+#[derive(Debug, Default)]
+pub struct CacheTags {
+    ids: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+// Tags match case-insensitively, like genres do
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_ascii_lowercase()
+}
+
+impl CacheTags {
+    pub async fn add<'a>(&self, id: &str, tags: impl IntoIterator<Item = &'a str>) {
+        let mut locked_ids = self.ids.lock().await;
+        for tag in tags {
+            locked_ids
+                .entry(normalize_tag(tag))
+                .or_default()
+                .insert(id.to_string());
+        }
+    }
+
+    /// Forgets `tag`, returning every id that was tagged with it
+    pub async fn take(&self, tag: &str) -> HashSet<String> {
+        let mut locked_ids = self.ids.lock().await;
+        locked_ids.remove(&normalize_tag(tag)).unwrap_or_default()
+    }
+}
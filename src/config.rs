@@ -0,0 +1,230 @@
+use crate::cors::AllowedOrigins;
+use crate::persist;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
+const DEFAULT_HOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 60;
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(1000).unwrap();
+
+/// Where cached movies live
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheBackend {
+    /// Per-process LRU
+    #[default]
+    Memory,
+    /// Shared between instances, at `REDIS_URL`
+    Redis,
+}
+
+impl FromStr for CacheBackend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "memory" => Ok(CacheBackend::Memory),
+            "redis" => Ok(CacheBackend::Redis),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Every setting the server reads from the environment
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `HOST` and `PORT`
+    pub addr: SocketAddr,
+    /// `CACHE_BACKEND`, `memory` or `redis`
+    pub cache_backend: CacheBackend,
+    /// `REDIS_URL`, only used by the redis cache backend
+    pub redis_url: String,
+    /// `CACHE_CAPACITY`, only used by the memory cache backend
+    pub cache_capacity: NonZeroUsize,
+    /// `CACHE_TTL_SECS`
+    pub cache_ttl: Duration,
+    /// `CACHE_WARM_COUNT`, movies copied into the cache at startup. 0 disables warming.
+    pub cache_warm_count: usize,
+    /// `DATABASE_URL`, SQLite is used instead of the in-memory db when set
+    pub database_url: Option<String>,
+    /// `MOVIES_DB_PATH`, where the in-memory db is persisted
+    pub db_path: PathBuf,
+    /// `API_KEY`, required on mutating requests. Auth is disabled when unset.
+    pub api_key: Option<String>,
+    /// `API_KEY_PROTECT_READS`, also require the API key on reads
+    pub api_key_protect_reads: bool,
+    /// `RATE_LIMIT_PER_MINUTE`, writes allowed per client IP. 0 disables rate limiting.
+    pub rate_limit_per_minute: u32,
+    /// `TRUST_FORWARDED_FOR`, rate limit by `X-Forwarded-For` instead of the peer address. Only
+    /// safe behind a proxy that overwrites the header.
+    pub trust_forwarded_for: bool,
+    /// `ALLOWED_ORIGINS`, comma separated or `*`. CORS is disabled when unset.
+    pub allowed_origins: Option<AllowedOrigins>,
+    /// `MAX_BODY_BYTES`, larger request bodies are rejected with a 413
+    pub max_body_bytes: usize,
+    /// `WEBHOOK_URL`, notified of every created movie when set
+    pub webhook_url: Option<reqwest::Url>,
+    /// `REQUEST_TIMEOUT_SECS`, handlers taking longer are answered with a 408
+    pub request_timeout: Duration,
+    /// `SWAGGER_UI`, also serve Swagger UI at `/swagger-ui`
+    pub swagger_ui: bool,
+    /// `AUDIT_LOG_PATH`, mutations are also appended here as JSON lines when set
+    pub audit_log_path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    var: &'static str,
+    value: String,
+    expected: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {}={:?}, expected {}",
+            self.var, self.value, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Unset falls back to the default, set but unparseable is an error rather than silently ignored
+fn parse_var<T: FromStr>(
+    var: &'static str,
+    expected: &'static str,
+    default: T,
+) -> Result<T, ConfigError> {
+    match std::env::var(var) {
+        Ok(value) => value.parse().map_err(|_| ConfigError {
+            var,
+            value,
+            expected,
+        }),
+        Err(_) => Ok(default),
+    }
+}
+
+// Everything a fresh deployment gets with no environment set
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: SocketAddr::new(DEFAULT_HOST, DEFAULT_PORT),
+            cache_backend: CacheBackend::default(),
+            redis_url: DEFAULT_REDIS_URL.to_string(),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            cache_warm_count: 0,
+            database_url: None,
+            db_path: persist::DEFAULT_DB_PATH.into(),
+            api_key: None,
+            api_key_protect_reads: false,
+            rate_limit_per_minute: DEFAULT_RATE_LIMIT_PER_MINUTE,
+            trust_forwarded_for: false,
+            allowed_origins: None,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            webhook_url: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            swagger_ui: false,
+            audit_log_path: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = Config::default();
+        let host = parse_var("HOST", "an IP address", defaults.addr.ip())?;
+        let port = parse_var("PORT", "a port number (0-65535)", defaults.addr.port())?;
+        let cache_backend = parse_var("CACHE_BACKEND", "memory or redis", defaults.cache_backend)?;
+        let cache_capacity = parse_var(
+            "CACHE_CAPACITY",
+            "a positive number of entries",
+            defaults.cache_capacity,
+        )?;
+        let cache_ttl = parse_var(
+            "CACHE_TTL_SECS",
+            "a number of seconds",
+            defaults.cache_ttl.as_secs(),
+        )?;
+        let cache_warm_count = parse_var(
+            "CACHE_WARM_COUNT",
+            "a number of movies",
+            defaults.cache_warm_count,
+        )?;
+
+        let rate_limit_per_minute = parse_var(
+            "RATE_LIMIT_PER_MINUTE",
+            "a number of requests",
+            defaults.rate_limit_per_minute,
+        )?;
+        let trust_forwarded_for = parse_var(
+            "TRUST_FORWARDED_FOR",
+            "true or false",
+            defaults.trust_forwarded_for,
+        )?;
+        let allowed_origins = match std::env::var("ALLOWED_ORIGINS") {
+            Ok(value) => Some(value.parse().map_err(|_| ConfigError {
+                var: "ALLOWED_ORIGINS",
+                value,
+                expected: "a comma separated list of origins or *",
+            })?),
+            Err(_) => defaults.allowed_origins,
+        };
+        let max_body_bytes = parse_var(
+            "MAX_BODY_BYTES",
+            "a number of bytes",
+            defaults.max_body_bytes,
+        )?;
+        let webhook_url = match std::env::var("WEBHOOK_URL") {
+            Ok(value) => Some(value.parse().map_err(|_| ConfigError {
+                var: "WEBHOOK_URL",
+                value,
+                expected: "a URL",
+            })?),
+            Err(_) => defaults.webhook_url,
+        };
+        let request_timeout = parse_var(
+            "REQUEST_TIMEOUT_SECS",
+            "a number of seconds",
+            defaults.request_timeout.as_secs(),
+        )?;
+        let swagger_ui = parse_var("SWAGGER_UI", "true or false", defaults.swagger_ui)?;
+        let api_key_protect_reads = parse_var(
+            "API_KEY_PROTECT_READS",
+            "true or false",
+            defaults.api_key_protect_reads,
+        )?;
+
+        Ok(Config {
+            addr: SocketAddr::new(host, port),
+            cache_backend,
+            redis_url: std::env::var("REDIS_URL").unwrap_or(defaults.redis_url),
+            cache_capacity,
+            cache_ttl: Duration::from_secs(cache_ttl),
+            cache_warm_count,
+            database_url: std::env::var("DATABASE_URL").ok(),
+            db_path: std::env::var("MOVIES_DB_PATH").map_or(defaults.db_path, PathBuf::from),
+            api_key: std::env::var("API_KEY").ok().filter(|key| !key.is_empty()),
+            api_key_protect_reads,
+            rate_limit_per_minute,
+            trust_forwarded_for,
+            allowed_origins,
+            max_body_bytes,
+            webhook_url,
+            request_timeout: Duration::from_secs(request_timeout),
+            swagger_ui,
+            audit_log_path: std::env::var("AUDIT_LOG_PATH").ok().map(PathBuf::from),
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use crate::auth::API_KEY_HEADER;
+use crate::idempotency::IDEMPOTENCY_KEY_HEADER;
+use crate::request_id::REQUEST_ID_HEADER;
+use axum::extract::Request;
+use axum::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Origins browsers may call the API from, parsed from `ALLOWED_ORIGINS`
+#[derive(Debug, Clone)]
+pub enum AllowedOrigins {
+    /// `*`, meant for local development
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+impl std::str::FromStr for AllowedOrigins {
+    type Err = header::InvalidHeaderValue;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.trim() == "*" {
+            return Ok(AllowedOrigins::Any);
+        }
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(HeaderValue::from_str)
+            .collect::<Result<_, _>>()
+            .map(AllowedOrigins::List)
+    }
+}
+
+pub fn cors_layer(origins: &AllowedOrigins) -> CorsLayer {
+    let allow_origin = match origins {
+        AllowedOrigins::Any => AllowOrigin::any(),
+        AllowedOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::IF_MATCH,
+            header::IF_NONE_MATCH,
+            API_KEY_HEADER.clone(),
+            IDEMPOTENCY_KEY_HEADER.clone(),
+            REQUEST_ID_HEADER.clone(),
+        ])
+        .expose_headers([
+            header::ETAG,
+            header::LOCATION,
+            header::WARNING,
+            REQUEST_ID_HEADER.clone(),
+        ])
+}
+
+/// `CorsLayer` answers preflights with an empty `200`, turn that into a `204 No Content`. Has to
+/// wrap the `CorsLayer` to see its response.
+pub async fn preflight_no_content(req: Request, next: Next) -> Response {
+    let is_preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(HeaderName::from_static("access-control-request-method"));
+
+    let mut response = next.run(req).await;
+    if is_preflight && response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::NO_CONTENT;
+    }
+    response
+}
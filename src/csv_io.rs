@@ -0,0 +1,214 @@
+use crate::audit::Actor;
+use crate::error::{ApiError, ErrorBody};
+use crate::{AppState, Movie};
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use utoipa::ToSchema;
+
+const CSV_HEADER: &[u8] = b"id,name,year,was_good\n";
+const CSV_COLUMNS: [&str; 4] = ["id", "name", "year", "was_good"];
+
+/// One CSV row, fields in the same order as `CSV_HEADER`
+#[derive(Serialize, Deserialize, Debug)]
+struct CsvMovie {
+    id: String,
+    name: String,
+    year: u16,
+    was_good: bool,
+}
+
+impl From<Movie> for CsvMovie {
+    fn from(movie: Movie) -> Self {
+        CsvMovie {
+            id: movie.id,
+            name: movie.name,
+            year: movie.year,
+            was_good: movie.was_good,
+        }
+    }
+}
+
+impl From<CsvMovie> for Movie {
+    fn from(record: CsvMovie) -> Self {
+        Movie {
+            id: crate::normalize_id(&record.id),
+            name: record.name,
+            year: record.year,
+            was_good: record.was_good,
+            genres: Vec::new(),
+            rating: None,
+            deleted_at: None,
+            version: 1,
+        }
+    }
+}
+
+// Serializes a single record, the csv crate takes care of quoting commas and quotes in names
+fn csv_row(record: &CsvMovie) -> Result<Bytes, csv::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.serialize(record)?;
+    let bytes = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(Bytes::from(bytes))
+}
+
+/// Streams the whole catalog one row at a time rather than building one big string
+#[utoipa::path(
+    get,
+    path = "/movies/export.csv",
+    responses((status = 200, description = "Every movie as CSV", body = String, content_type = "text/csv")),
+    tag = "movies"
+)]
+pub async fn export_csv(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let mut movies = state.db.list().await?;
+    movies.retain(|movie| !movie.is_deleted());
+    movies.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let header = std::iter::once(Bytes::from_static(CSV_HEADER));
+    let rows = movies
+        .into_iter()
+        .filter_map(|movie| match csv_row(&CsvMovie::from(movie)) {
+            Ok(row) => Some(row),
+            Err(err) => {
+                tracing::error!("failed to write CSV row: {err}");
+                None
+            }
+        });
+    let chunks = header.chain(rows).map(Ok::<_, Infallible>);
+
+    let body = Body::from_stream(tokio_stream::iter(chunks));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"movies.csv\"",
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct ImportError {
+    // 1-based, not counting the header
+    row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    message: String,
+}
+
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct ImportSummary {
+    imported: usize,
+    skipped: usize,
+    errors: Vec<ImportError>,
+}
+
+/// Accepts the same format `export_csv` produces, bad rows are skipped and reported
+/// rather than failing the whole import
+#[utoipa::path(
+    post,
+    path = "/movies/import",
+    request_body(content = String, content_type = "text/csv", description = "Header row then one movie per row"),
+    responses(
+        (status = 200, description = "How many rows were imported, and why the rest weren't", body = ImportSummary),
+        (status = 400, description = "Missing header columns", body = ErrorBody),
+        (status = 415, description = "Not text/csv", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+pub async fn import_csv(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    actor: Actor,
+    body: Bytes,
+) -> Result<Json<ImportSummary>, ApiError> {
+    let is_csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/csv"));
+    if !is_csv {
+        return Err(ApiError::UnsupportedMediaType(
+            "expected request with `Content-Type: text/csv`".to_string(),
+        ));
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_ref());
+    let header_ok = reader.headers().is_ok_and(|found| {
+        CSV_COLUMNS
+            .iter()
+            .all(|col| found.iter().any(|h| h == *col))
+    });
+    if !header_ok {
+        return Err(ApiError::BadRequest(format!(
+            "CSV header must contain {}",
+            CSV_COLUMNS.join(",")
+        )));
+    }
+
+    let mut summary = ImportSummary::default();
+    // Row numbers of the movies handed to the store, to report conflicts against
+    let mut valid = Vec::new();
+    let mut rows = Vec::new();
+    for (index, record) in reader.deserialize::<CsvMovie>().enumerate() {
+        let row = index + 1;
+        let movie = match record {
+            Ok(record) => Movie::from(record),
+            Err(err) => {
+                summary.errors.push(ImportError {
+                    row,
+                    id: None,
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+        if let Err(err) = movie.validate() {
+            let message = err
+                .fields
+                .iter()
+                .map(|field| format!("{}: {}", field.field, field.message))
+                .collect::<Vec<_>>()
+                .join(", ");
+            summary.errors.push(ImportError {
+                row,
+                id: Some(movie.id),
+                message,
+            });
+            continue;
+        }
+        valid.push(movie);
+        rows.push(row);
+    }
+
+    // One call so the store only takes its write lock once for the whole file
+    let created = state.db.insert_many(valid.clone()).await?;
+    for ((movie, row), created) in valid.into_iter().zip(rows).zip(created) {
+        if created {
+            summary.imported += 1;
+            state.movie_created(movie, &actor).await;
+        } else {
+            summary.errors.push(ImportError {
+                row,
+                id: Some(movie.id.clone()),
+                message: format!("movie `{}` already exists", movie.id),
+            });
+        }
+    }
+
+    summary.errors.sort_by_key(|err| err.row);
+    summary.skipped = summary.errors.len();
+    Ok(Json(summary))
+}
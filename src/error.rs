@@ -0,0 +1,198 @@
+use crate::store::StoreError;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Errors returned by the API, serialized as `{ "error": "...", "code": "..." }`
+#[derive(Debug)]
+pub enum ApiError {
+    /// No movie with the given id
+    NotFound(String),
+    /// Nothing matched a query that should return a single movie
+    NoMatch(&'static str),
+    /// A movie with the given id already exists
+    Conflict(String),
+    /// A movie with the same name and year already exists under this id
+    Duplicate(String),
+    /// The movie has been written since the version the client sent
+    VersionMismatch {
+        movie_id: String,
+        expected: u64,
+    },
+    /// A write that has to say which version it expects didn't
+    PreconditionRequired(&'static str),
+    /// The Idempotency-Key was already used for a different request
+    IdempotencyKeyReused(String),
+    /// A different request with this Idempotency-Key is still being processed
+    IdempotencyKeyInFlight(String),
+    BadRequest(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    Unauthorized(&'static str),
+    TooManyRequests {
+        retry_after_secs: u64,
+    },
+    Validation(ValidationError),
+    /// The db failed, the details are logged rather than returned to the client
+    Internal(StoreError),
+}
+
+/// A single field that failed validation
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Every field that failed validation, rather than just the first one
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ValidationError {
+    pub fields: Vec<FieldError>,
+}
+
+impl ValidationError {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.fields.push(FieldError {
+            field,
+            message: message.into(),
+        });
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationError> {
+        if self.fields.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        ApiError::Internal(err)
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(err: ValidationError) -> Self {
+        ApiError::Validation(err)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) | ApiError::NoMatch(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_)
+            | ApiError::Duplicate(_)
+            | ApiError::VersionMismatch { .. }
+            | ApiError::IdempotencyKeyInFlight(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Validation(_) | ApiError::IdempotencyKeyReused(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) | ApiError::NoMatch(_) => "NOT_FOUND",
+            ApiError::Conflict(_)
+            | ApiError::Duplicate(_)
+            | ApiError::VersionMismatch { .. }
+            | ApiError::IdempotencyKeyInFlight(_) => "CONFLICT",
+            ApiError::PreconditionRequired(_) => "PRECONDITION_REQUIRED",
+            ApiError::BadRequest(_) => "BAD_REQUEST",
+            ApiError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            ApiError::UnsupportedMediaType(_) => "UNSUPPORTED_MEDIA_TYPE",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::TooManyRequests { .. } => "TOO_MANY_REQUESTS",
+            ApiError::Validation(_) | ApiError::IdempotencyKeyReused(_) => "UNPROCESSABLE_ENTITY",
+            ApiError::Internal(_) => "INTERNAL_SERVER_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(movie_id) => write!(f, "movie `{movie_id}` not found"),
+            ApiError::Conflict(movie_id) => write!(f, "movie `{movie_id}` already exists"),
+            ApiError::Duplicate(movie_id) => {
+                write!(
+                    f,
+                    "movie `{movie_id}` already exists with the same name and year"
+                )
+            }
+            ApiError::BadRequest(msg)
+            | ApiError::PayloadTooLarge(msg)
+            | ApiError::UnsupportedMediaType(msg) => f.write_str(msg),
+            ApiError::VersionMismatch { movie_id, expected } => write!(
+                f,
+                "movie `{movie_id}` has changed since version {expected}, fetch it and try again"
+            ),
+            ApiError::NoMatch(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::PreconditionRequired(msg) => f.write_str(msg),
+            ApiError::TooManyRequests { retry_after_secs } => {
+                write!(f, "rate limit exceeded, retry in {retry_after_secs}s")
+            }
+            ApiError::Validation(err) => {
+                let fields: Vec<&str> = err.fields.iter().map(|e| e.field).collect();
+                write!(f, "invalid fields: {}", fields.join(", "))
+            }
+            ApiError::IdempotencyKeyReused(key) => write!(
+                f,
+                "Idempotency-Key `{key}` was already used for a different request"
+            ),
+            ApiError::IdempotencyKeyInFlight(key) => write!(
+                f,
+                "a different request with Idempotency-Key `{key}` is still in progress"
+            ),
+            ApiError::Internal(_) => f.write_str("internal server error"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(err) = &self {
+            tracing::error!("{err}");
+        }
+        let fields = match &self {
+            ApiError::Validation(err) => err.fields.clone(),
+            _ => Vec::new(),
+        };
+        let body = ErrorBody {
+            error: self.to_string(),
+            code: self.code(),
+            fields,
+        };
+        let mut response = (self.status(), Json(body)).into_response();
+        if let ApiError::TooManyRequests { retry_after_secs } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after_secs.into());
+        }
+        response
+    }
+}
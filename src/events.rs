@@ -0,0 +1,58 @@
+use crate::{AppState, Movie};
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use std::convert::Infallible;
+use tokio_stream::wrappers::{BroadcastStream, WatchStream};
+use tokio_stream::{Stream, StreamExt};
+
+/// How many events a slow subscriber can fall behind before it starts missing them
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Published by the mutating handlers, streamed to `GET /movies/events` subscribers
+#[derive(Debug, Clone)]
+pub enum MovieEvent {
+    Created(Movie),
+    Updated(Movie),
+    Deleted(String),
+}
+
+impl MovieEvent {
+    fn into_sse(self) -> Event {
+        let event = match &self {
+            MovieEvent::Created(_) => Event::default().event("created"),
+            MovieEvent::Updated(_) => Event::default().event("updated"),
+            MovieEvent::Deleted(_) => Event::default().event("deleted"),
+        };
+        match self {
+            MovieEvent::Created(movie) | MovieEvent::Updated(movie) => {
+                event.json_data(movie).expect("a Movie always serializes")
+            }
+            MovieEvent::Deleted(movie_id) => event.data(movie_id),
+        }
+    }
+}
+
+impl AppState {
+    pub fn publish(&self, event: MovieEvent) {
+        // Only fails when nobody is subscribed, which is fine
+        let _ = self.events.send(event);
+    }
+}
+
+pub async fn movie_events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // A lagging subscriber just skips whatever it missed instead of ending the stream
+    let events = BroadcastStream::new(state.events.subscribe())
+        .filter_map(|event| event.ok())
+        .map(Some);
+    // These streams never finish on their own, end them on shutdown so it doesn't hang waiting
+    let shutdown = WatchStream::new(state.shutdown.subscribe())
+        .filter(|shutting_down| *shutting_down)
+        .map(|_| None);
+    let stream = events
+        .merge(shutdown)
+        .map_while(|event| event)
+        .map(|event| Ok(event.into_sse()));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
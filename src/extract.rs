@@ -0,0 +1,63 @@
+use crate::error::ApiError;
+use axum::async_trait;
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{FromRequest, FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+/// Drop-in for `axum::Json` whose rejections are `ApiError`s, so clients get a JSON body saying
+/// what was wrong with their payload instead of axum's plain text
+#[derive(FromRequest, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// The `:movie_id` path segment, normalized the same way stored ids are (see `normalize_id`)
+#[derive(Debug, Clone)]
+pub struct MovieId(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for MovieId {
+    type Rejection = PathRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(movie_id) = Path::<String>::from_request_parts(parts, state).await?;
+        Ok(MovieId(crate::normalize_id(&movie_id)))
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            // Report serde's own message, e.g. "expected value at line 1 column 5"
+            JsonRejection::JsonSyntaxError(err) => {
+                ApiError::BadRequest(format!("invalid JSON: {}", innermost_message(&err)))
+            }
+            JsonRejection::JsonDataError(err) => {
+                ApiError::BadRequest(format!("invalid JSON: {}", innermost_message(&err)))
+            }
+            JsonRejection::MissingJsonContentType(_) => ApiError::UnsupportedMediaType(
+                "expected request with `Content-Type: application/json`".to_string(),
+            ),
+            rejection if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                ApiError::PayloadTooLarge(rejection.body_text())
+            }
+            rejection => ApiError::BadRequest(rejection.body_text()),
+        }
+    }
+}
+
+fn innermost_message(err: &dyn std::error::Error) -> String {
+    let mut err = err;
+    while let Some(source) = err.source() {
+        err = source;
+    }
+    err.to_string()
+}
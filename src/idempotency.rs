@@ -0,0 +1,194 @@
+use crate::error::ApiError;
+use axum::http::{HeaderMap, HeaderName};
+use lru::LruCache;
+use serde::Serialize;
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OnceCell};
+
+pub static IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// How long a key is remembered, retries after this are processed again
+pub const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const MAX_KEY_LEN: usize = 255;
+
+// Past this many keys the oldest is forgotten even if it hasn't expired, a retry with it is then
+// processed again
+const MAX_TRACKED_KEYS: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+struct Entry<T> {
+    result: Arc<OnceCell<T>>,
+    // Requests currently running or waiting on `result`, see `InFlight`
+    in_flight: Arc<AtomicUsize>,
+    // Of the request that first used the key, see `run`
+    fingerprint: u64,
+    created_at: Instant,
+}
+
+impl<T> Entry<T> {
+    fn new(fingerprint: u64, created_at: Instant) -> Self {
+        Entry {
+            result: Arc::default(),
+            in_flight: Arc::default(),
+            fingerprint,
+            created_at,
+        }
+    }
+}
+
+// Counts a request against its entry until dropped, also when the client goes away mid-request
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn start(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        InFlight(count.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Results of requests sent with an `Idempotency-Key`, so a retry gets the original answer
+/// instead of being processed twice
+pub struct IdempotencyKeys<T> {
+    ttl: Duration,
+    // Never promoted on lookup, so the least recently used entry is always the oldest
+    entries: Mutex<LruCache<String, Entry<T>>>,
+}
+
+impl<T: Clone> IdempotencyKeys<T> {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyKeys {
+            ttl,
+            entries: Mutex::new(LruCache::new(MAX_TRACKED_KEYS)),
+        }
+    }
+
+    /// Runs `f` the first time `key` is seen and returns its result for every repeat until the
+    /// key expires. Concurrent repeats wait on the first one, errors aren't remembered so the
+    /// client can retry them. `fingerprint` identifies the request: reusing a key for a different
+    /// request is rejected while the first one is running or once it has a result, and only
+    /// allowed after it failed.
+    pub async fn run<F, Fut>(&self, key: String, fingerprint: u64, f: F) -> Result<T, ApiError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let (result, _in_flight) = {
+            let now = Instant::now();
+            let mut locked_entries = self.entries.lock().await;
+            match locked_entries.peek_mut(&key) {
+                Some(entry) if now.duration_since(entry.created_at) >= self.ttl => {
+                    *entry = Entry::new(fingerprint, now);
+                }
+                Some(entry) if entry.fingerprint != fingerprint => {
+                    if entry.result.initialized() {
+                        return Err(ApiError::IdempotencyKeyReused(key));
+                    }
+                    if entry.in_flight.load(Ordering::SeqCst) > 0 {
+                        return Err(ApiError::IdempotencyKeyInFlight(key));
+                    }
+                    // The first request failed, so this is a retry with a corrected request
+                    *entry = Entry::new(fingerprint, now);
+                }
+                Some(_) => {}
+                None => {
+                    locked_entries.push(key.clone(), Entry::new(fingerprint, now));
+                }
+            }
+            // Just inserted if it wasn't there, and nothing can evict it while the lock is held
+            let entry = locked_entries.peek(&key).expect("entry was just inserted");
+            (entry.result.clone(), InFlight::start(&entry.in_flight))
+        };
+
+        result.get_or_try_init(f).await.cloned()
+    }
+}
+
+/// Identifies a request for `IdempotencyKeys::run`, only has to be stable within this process
+pub fn fingerprint(request: &impl Serialize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(request)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+// None when the header wasn't sent
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get(&IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key.to_string())),
+        _ => Err(ApiError::BadRequest(format!(
+            "`{IDEMPOTENCY_KEY_HEADER}` must be 1 to {MAX_KEY_LEN} visible ASCII characters"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn a_different_request_is_turned_away_while_the_first_is_running() {
+        let keys = Arc::new(IdempotencyKeys::<u32>::new(IDEMPOTENCY_TTL));
+        let (finish, finished) = oneshot::channel::<()>();
+        let (started, running) = oneshot::channel::<()>();
+        let first = tokio::spawn({
+            let keys = keys.clone();
+            async move {
+                keys.run("key".to_string(), 1, || async {
+                    started.send(()).unwrap();
+                    finished.await.unwrap();
+                    Ok(1)
+                })
+                .await
+            }
+        });
+        running.await.unwrap();
+
+        let err = keys
+            .run("key".to_string(), 2, || async { Ok(2) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+
+        finish.send(()).unwrap();
+        assert_eq!(first.await.unwrap().unwrap(), 1);
+        // The first request's retry is still answered from the key
+        let retried = keys.run("key".to_string(), 1, || async { Ok(3) }).await;
+        assert_eq!(retried.unwrap(), 1);
+        let err = keys
+            .run("key".to_string(), 2, || async { Ok(2) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn a_different_request_may_reuse_the_key_after_a_failure() {
+        let keys = IdempotencyKeys::<u32>::new(IDEMPOTENCY_TTL);
+        let failed = keys
+            .run("key".to_string(), 1, || async {
+                Err(ApiError::BadRequest("nope".to_string()))
+            })
+            .await;
+        assert!(failed.is_err());
+
+        let corrected = keys.run("key".to_string(), 2, || async { Ok(2) }).await;
+        assert_eq!(corrected.unwrap(), 2);
+    }
+}
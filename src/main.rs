@@ -1,60 +1,1337 @@
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::routing::{get, post};
-use axum::{Json, Router};
+mod admin;
+mod audit;
+mod auth;
+mod cache;
+mod config;
+mod cors;
+mod csv_io;
+mod error;
+mod events;
+mod extract;
+mod idempotency;
+mod metrics;
+mod openapi;
+mod persist;
+mod rate_limit;
+mod request_id;
+mod store;
+#[cfg(test)]
+mod tests;
+mod v1;
+mod webhook;
+
+use audit::{Actor, AuditAction, AuditLog};
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use cache::{Cache, CacheTags, MemoryCache, RedisCache};
+use config::{CacheBackend, Config};
+use dashmap::DashMap;
+use error::{ApiError, ErrorBody, FieldError, ValidationError};
+use events::MovieEvent;
+use extract::{Json, MovieId};
+use idempotency::IdempotencyKeys;
+use metrics::Metrics;
+use rand::seq::IndexedRandom;
+use rate_limit::RateLimiter;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use store::{InMemoryStore, MovieStore, SqliteStore, StoreError};
+use tokio::sync::{broadcast, watch, Mutex, OnceCell};
+use tokio_stream::{Stream, StreamExt};
+use tower_http::compression::CompressionLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+use utoipa::{IntoParams, ToSchema};
+use webhook::Webhook;
+
+type InflightLoad = Arc<OnceCell<Option<Movie>>>;
 
 #[derive(Clone)]
 struct AppState {
-    // Use individual member locks to help avoid dead lock conditions
-    pub db: Arc<RwLock<HashMap<String, Movie>>>,
-    pub cache: Arc<RwLock<HashMap<String, Movie>>>,
+    // In-memory or SQLite, see DATABASE_URL. Each backend does its own locking.
+    pub db: Arc<dyn MovieStore>,
+    // In-process or Redis, see CACHE_BACKEND
+    pub cache: Arc<dyn Cache>,
+    // Genres of everything cached, for POST /admin/cache/invalidate
+    pub cache_tags: Arc<CacheTags>,
+    pub metrics: Arc<Metrics>,
+    // db loads currently running, concurrent misses for the same id wait on these instead of
+    // all hitting the db
+    pub inflight: Arc<Mutex<HashMap<String, InflightLoad>>>,
+    // Bumped on every write to an id, so a load that raced a write knows its result is stale
+    pub generations: Arc<DashMap<String, u64>>,
+    // Set once startup has finished, cleared again when shutting down
+    pub ready: Arc<AtomicBool>,
+    // Auth is disabled when this is None
+    pub api_key: Option<Arc<str>>,
+    // Writes aren't rate limited when this is None
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Every create/update/delete is published here for the SSE stream
+    pub events: broadcast::Sender<MovieEvent>,
+    // Flips to true once shutdown starts, for anything long running that needs to wind down
+    pub shutdown: Arc<watch::Sender<bool>>,
+    pub webhook: Option<Arc<Webhook>>,
+    // POST /movie results by Idempotency-Key
+    pub idempotency: Arc<IdempotencyKeys<CreatedMovie>>,
+    // Everything the router is built from, see `app`
+    pub config: Arc<Config>,
+    pub audit: Arc<AuditLog>,
+}
+
+impl AppState {
+    // Handlers only see the MovieStore and Cache traits, so any backend (or a mock) can be
+    // plugged in here
+    fn new(db: Arc<dyn MovieStore>, cache: Arc<dyn Cache>) -> Self {
+        AppState {
+            db,
+            cache,
+            cache_tags: Arc::new(CacheTags::default()),
+            metrics: Arc::new(Metrics::default()),
+            inflight: Arc::new(Mutex::new(HashMap::default())),
+            generations: Arc::new(DashMap::new()),
+            ready: Arc::new(AtomicBool::new(false)),
+            api_key: None,
+            rate_limiter: None,
+            events: broadcast::channel(events::EVENT_CHANNEL_CAPACITY).0,
+            shutdown: Arc::new(watch::channel(false).0),
+            webhook: None,
+            idempotency: Arc::new(IdempotencyKeys::new(idempotency::IDEMPOTENCY_TTL)),
+            config: Arc::new(Config::default()),
+            audit: Arc::new(AuditLog::default()),
+        }
+    }
+
+    // Everything that has to happen after a movie is stored for the first time
+    async fn movie_created(&self, movie: Movie, actor: &Actor) {
+        self.audit
+            .record(AuditAction::Create, &movie.id, actor)
+            .await;
+        self.metrics.movie_added();
+        // Drop anything left over so the cache can't shadow a re-created movie
+        self.invalidate(&movie.id).await;
+        if let Some(webhook) = &self.webhook {
+            webhook.movie_created(movie.clone());
+        }
+        self.publish(MovieEvent::Created(movie));
+    }
+
+    fn generation(&self, movie_id: &str) -> u64 {
+        self.generations
+            .get(movie_id)
+            .map_or(0, |generation| *generation)
+    }
+
+    // Call after every db write to `movie_id`, never before
+    async fn invalidate(&self, movie_id: &str) {
+        *self.generations.entry(movie_id.to_string()).or_default() += 1;
+        // Misses from here on start a fresh load instead of joining one that predates the write
+        self.inflight.lock().await.remove(movie_id);
+        self.cache.invalidate(movie_id).await;
+    }
+
+    // `generation` is what it was before `movie` was read from the db. If a write has bumped it
+    // since, the write's own invalidate may already have run, so undo the set ourselves. Either
+    // way a stale movie can't outlive both checks.
+    async fn cache_if_current(&self, movie: Movie, generation: u64) {
+        let movie_id = movie.id.clone();
+        self.cache_tags
+            .add(&movie_id, movie.genres.iter().map(String::as_str))
+            .await;
+        self.cache.set(movie).await;
+        if self.generation(&movie_id) != generation {
+            self.cache.invalidate(&movie_id).await;
+        }
+    }
+
+    // Returns how many ids were tagged, whether or not they were still cached
+    async fn invalidate_tag(&self, tag: &str) -> usize {
+        let movie_ids = self.cache_tags.take(tag).await;
+        for movie_id in &movie_ids {
+            self.invalidate(movie_id).await;
+        }
+        movie_ids.len()
+    }
+
+    // Copies up to `count` movies into the cache. Neither store tracks insertion order, so which
+    // ones is arbitrary.
+    async fn warm_cache(&self, count: usize) -> Result<usize, StoreError> {
+        let count = self.cache.capacity().map_or(count, |cap| count.min(cap));
+        let mut warmed = 0;
+        for movie in self
+            .db
+            .list()
+            .await?
+            .into_iter()
+            .filter(|movie| !movie.is_deleted())
+            .take(count)
+        {
+            // Warming runs before the server accepts requests, so nothing can have written since
+            self.cache_if_current(movie, 0).await;
+            warmed += 1;
+        }
+        Ok(warmed)
+    }
+
+    // Cache first, falling through to the db and caching whatever it finds
+    async fn load_movie(&self, movie_id: &str) -> Result<Option<Movie>, StoreError> {
+        if let Some(movie) = self.cache.get(movie_id).await {
+            self.metrics.cache_hit();
+            return Ok(Some(movie));
+        }
+        self.metrics.cache_miss();
+
+        let load = {
+            let mut locked_inflight = self.inflight.lock().await;
+            locked_inflight
+                .entry(movie_id.to_string())
+                .or_default()
+                .clone()
+        };
+
+        // Only the first caller runs this, everyone else awaits its result. If it fails the
+        // cell stays empty and the next waiter retries.
+        let movie = load
+            .get_or_try_init(|| async {
+                let generation = self.generation(movie_id);
+                // Soft-deleted movies are never cached, so a hit is always a live movie
+                let movie = self
+                    .db
+                    .get(movie_id)
+                    .await?
+                    .filter(|movie| !movie.is_deleted());
+                if let Some(movie) = &movie {
+                    self.cache_if_current(movie.clone(), generation).await;
+                    tracing::debug!("Found Movie: {:?}", movie);
+                }
+                Ok::<_, StoreError>(movie)
+            })
+            .await
+            .cloned();
+
+        // Later misses should start a fresh load rather than reuse this result
+        {
+            let mut locked_inflight = self.inflight.lock().await;
+            if locked_inflight
+                .get(movie_id)
+                .is_some_and(|current| Arc::ptr_eq(current, &load))
+            {
+                locked_inflight.remove(movie_id);
+            }
+        }
+        movie
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Ids are case-insensitive, see `normalize_id`
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 struct Movie {
     pub id: String,
     pub name: String,
     pub year: u16,
     pub was_good: bool,
+    // Missing in movies stored before genres were added
+    #[serde(default)]
+    pub genres: Vec<String>,
+    // 0.0 to 10.0, finer grained than was_good
+    #[serde(default)]
+    pub rating: Option<f32>,
+    // Unix seconds, set instead of removing the movie so it can be restored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    // Starts at 1 and is bumped by every write, 0 for movies stored before versions were added
+    #[serde(default)]
+    pub version: u64,
+}
+
+// Ids are stored and looked up trimmed and lowercased, so ` TT0111161` and `tt0111161` are the
+// same movie
+fn normalize_id(id: &str) -> String {
+    id.trim().to_lowercase()
+}
+
+// For duplicate detection only, names are stored as given
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+impl Movie {
+    fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    fn has_genre(&self, genre: &str) -> bool {
+        self.genres.iter().any(|g| g.eq_ignore_ascii_case(genre))
+    }
+}
+
+/// POST payload, the id may be omitted to have the server generate one
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+struct CreateMovie {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    pub year: u16,
+    pub was_good: bool,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub rating: Option<f32>,
+}
+
+impl CreateMovie {
+    fn into_movie(self) -> Movie {
+        Movie {
+            id: self
+                .id
+                .map_or_else(|| uuid::Uuid::new_v4().to_string(), |id| normalize_id(&id)),
+            name: self.name,
+            year: self.year,
+            was_good: self.was_good,
+            genres: self.genres,
+            rating: self.rating,
+            deleted_at: None,
+            version: 1,
+        }
+    }
+}
+
+/// PUT payload, the whole movie plus the version being replaced unless that's sent as If-Match
+#[derive(Deserialize, Debug, ToSchema)]
+struct ReplaceMovie {
+    pub id: String,
+    pub name: String,
+    pub year: u16,
+    pub was_good: bool,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub rating: Option<f32>,
+    pub version: Option<u64>,
+}
+
+impl ReplaceMovie {
+    // The version is only known once the stored movie has been checked, see `next_version`
+    fn into_movie(self) -> Movie {
+        Movie {
+            id: normalize_id(&self.id),
+            name: self.name,
+            year: self.year,
+            was_good: self.was_good,
+            genres: self.genres,
+            rating: self.rating,
+            deleted_at: None,
+            version: 0,
+        }
+    }
+}
+
+// The oldest surviving film is from 1888, anything past 2100 is a typo
+const VALID_YEARS: std::ops::RangeInclusive<u16> = 1888..=2100;
+const VALID_RATINGS: std::ops::RangeInclusive<f32> = 0.0..=10.0;
+
+impl Movie {
+    fn validate(&self) -> Result<(), ValidationError> {
+        let mut err = ValidationError::default();
+        if self.id.trim().is_empty() {
+            err.add("id", "must not be empty");
+        }
+        if self.name.trim().is_empty() {
+            err.add("name", "must not be empty");
+        }
+        if !VALID_YEARS.contains(&self.year) {
+            err.add(
+                "year",
+                format!(
+                    "must be between {} and {}",
+                    VALID_YEARS.start(),
+                    VALID_YEARS.end()
+                ),
+            );
+        }
+        // NaN isn't contained in any range so it's rejected here too
+        if self
+            .rating
+            .is_some_and(|rating| !VALID_RATINGS.contains(&rating))
+        {
+            err.add(
+                "rating",
+                format!(
+                    "must be between {} and {}",
+                    VALID_RATINGS.start(),
+                    VALID_RATINGS.end()
+                ),
+            );
+        }
+        err.into_result()
+    }
+}
+
+const MAX_BATCH_IDS: usize = 100;
+
+const DEFAULT_LIST_LIMIT: usize = 50;
+const MAX_LIST_LIMIT: usize = 500;
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+// Only an explicit NDJSON media type counts, `*/*` and anything else get the JSON array
+fn wants_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| {
+            let media = media.split(';').next().unwrap_or_default().trim();
+            media.eq_ignore_ascii_case(NDJSON_CONTENT_TYPE)
+        })
+}
+
+// One movie per line, written out as the body is polled rather than as one big array. A store
+// error mid-stream aborts the body, so the client sees a truncated response rather than a short
+// but complete looking one.
+fn ndjson_body(movies: impl Stream<Item = Result<Movie, StoreError>> + Send + 'static) -> Response {
+    let lines = movies.filter_map(|movie| match movie {
+        Ok(movie) => match serde_json::to_vec(&movie) {
+            Ok(mut line) => {
+                line.push(b'\n');
+                Some(Ok(Bytes::from(line)))
+            }
+            Err(err) => {
+                tracing::error!("failed to serialize movie {}: {err}", movie.id);
+                None
+            }
+        },
+        Err(err) => {
+            tracing::error!("failed to stream movies: {err}");
+            Some(Err(err))
+        }
+    });
+    (
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum SortField {
+    #[default]
+    Id,
+    Rating,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListParams {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+    // Soft-deleted movies are left out unless asked for
+    #[serde(default)]
+    pub include_deleted: bool,
+    pub year_min: Option<u16>,
+    pub year_max: Option<u16>,
+}
+
+// Inclusive, a missing bound leaves that side open
+fn year_range(
+    year_min: Option<u16>,
+    year_max: Option<u16>,
+) -> Result<std::ops::RangeInclusive<u16>, ApiError> {
+    let (min, max) = (year_min.unwrap_or(u16::MIN), year_max.unwrap_or(u16::MAX));
+    if min > max {
+        return Err(ApiError::BadRequest(format!(
+            "year_min ({min}) must not be greater than year_max ({max})"
+        )));
+    }
+    Ok(min..=max)
+}
+
+// Unrated movies always go last whichever the order, ties fall back to the id so pages are
+// deterministic
+fn sort_movies(movies: &mut [Movie], field: SortField, order: SortOrder) {
+    movies.sort_by(|a, b| {
+        let ordering = match field {
+            SortField::Id => a.id.cmp(&b.id),
+            SortField::Rating => match (a.rating, b.rating) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (Some(_), None) => return std::cmp::Ordering::Less,
+                (None, Some(_)) => return std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            },
+        };
+        let ordering = match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        ordering.then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+// Every provided filter has to match, no filters matches everything
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchParams {
+    // Case-insensitive substring
+    pub name: Option<String>,
+    pub year: Option<u16>,
+    pub was_good: Option<bool>,
+    // Case-insensitive, matches if the movie has this genre among others
+    pub genre: Option<String>,
+    pub year_min: Option<u16>,
+    pub year_max: Option<u16>,
+}
+
+impl SearchParams {
+    fn matches(&self, movie: &Movie, years: &std::ops::RangeInclusive<u16>) -> bool {
+        if let Some(name) = &self.name {
+            if !movie.name.to_lowercase().contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+        if self.year.is_some_and(|year| movie.year != year) || !years.contains(&movie.year) {
+            return false;
+        }
+        if self
+            .was_good
+            .is_some_and(|was_good| movie.was_good != was_good)
+        {
+            return false;
+        }
+        if let Some(genre) = &self.genre {
+            if !movie.has_genre(genre) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// The version, so the tag from a GET can be sent straight back as If-Match
+fn etag(movie: &Movie) -> String {
+    format!("\"{}\"", movie.version)
+}
+
+// The version a write expects to replace, as a bare number or an entity tag from `etag`
+fn if_match(headers: &HeaderMap) -> Result<Option<u64>, ApiError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+        .and_then(|version| version.parse().ok())
+        .map(Some)
+        .ok_or_else(|| ApiError::BadRequest("If-Match must be a movie version".to_string()))
+}
+
+// The version a write is stored at, once the stored movie is confirmed to still be at `expected`.
+// Writes that lost a race with another write get the same error as a stale version, the client
+// has to fetch the movie again either way.
+fn next_version(movie: &Movie, expected: u64) -> Result<u64, ApiError> {
+    let mismatch = || ApiError::VersionMismatch {
+        movie_id: movie.id.clone(),
+        expected,
+    };
+    if movie.version != expected {
+        return Err(mismatch());
+    }
+    movie.version.checked_add(1).ok_or_else(mismatch)
+}
+
+// If-None-Match can list several tags, weak or strong, or be `*`
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
 }
 
+#[utoipa::path(
+    get,
+    path = "/movie/{movie_id}",
+    params(("movie_id" = String, Path, description = "Case-insensitive, surrounding whitespace is ignored")),
+    responses(
+        (status = 200, description = "The movie", body = Movie, headers(("etag" = String))),
+        (status = 304, description = "Unchanged since the If-None-Match tag"),
+        (status = 404, description = "No such movie", body = ErrorBody),
+    ),
+    tag = "movies"
+)]
 async fn get_movie(
     State(state): State<AppState>,
-    Path(movie_id): Path<String>,
-) -> Result<Json<Movie>, StatusCode> {
-    // Scope so we don't hold the read lock
+    MovieId(movie_id): MovieId,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let Some(movie) = state.load_movie(&movie_id).await? else {
+        return Err(ApiError::NotFound(movie_id));
+    };
+
+    let etag = etag(&movie);
+    if if_none_match(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(([(header::ETAG, etag)], Json(movie)).into_response())
+}
+
+/// A successful POST /movie, kept so a retry with the same Idempotency-Key gets the same answer
+#[derive(Debug, Clone)]
+struct CreatedMovie {
+    pub movie: Movie,
+    // Id of an existing movie with the same name and year
+    pub duplicate_of: Option<String>,
+}
+
+impl IntoResponse for CreatedMovie {
+    fn into_response(self) -> Response {
+        let mut headers = HeaderMap::new();
+        if let Some(existing_id) = &self.duplicate_of {
+            // 299 is the catch-all "miscellaneous persistent warning"
+            let warning = format!(
+                "299 - \"possible duplicate of movie `{existing_id}` with the same name and year\""
+            );
+            if let Ok(value) = HeaderValue::from_str(&warning) {
+                headers.insert(header::WARNING, value);
+            }
+        }
+
+        // Hand back exactly what was stored and where to find it, saving the client a GET
+        let location = format!("{}/movie/{}", v1::PREFIX, self.movie.id);
+        (
+            StatusCode::CREATED,
+            headers,
+            [(header::LOCATION, location)],
+            Json(self.movie),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CreateParams {
+    // Reject likely duplicates with a 409 instead of only warning
+    #[serde(default)]
+    pub strict: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/movie",
+    params(
+        CreateParams,
+        ("idempotency-key" = Option<String>, Header, description = "Retries with the same key get the original response"),
+    ),
+    request_body = CreateMovie,
+    responses(
+        (status = 201, description = "Created, a Warning header flags a likely duplicate", body = Movie, headers(("location" = String), ("warning" = Option<String>))),
+        (status = 409, description = "The id is taken, a duplicate with ?strict=true, or a different request with the same Idempotency-Key is in progress", body = ErrorBody),
+        (status = 422, description = "Invalid fields, or the Idempotency-Key was used for a different request", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+async fn add_movie(
+    State(state): State<AppState>,
+    Query(params): Query<CreateParams>,
+    headers: HeaderMap,
+    actor: Actor,
+    Json(new_movie): Json<CreateMovie>,
+) -> Result<CreatedMovie, ApiError> {
+    let fingerprint = idempotency::fingerprint(&(params.strict, &new_movie));
+    let create = || async {
+        let movie = new_movie.into_movie();
+        movie.validate()?;
+
+        let duplicate_of = state
+            .db
+            .find_by_name_year(&movie.name, movie.year)
+            .await?
+            .map(|existing| existing.id)
+            // Re-posting the same id is reported as a plain conflict below
+            .filter(|existing_id| *existing_id != movie.id);
+        if let Some(existing_id) = &duplicate_of {
+            if params.strict {
+                return Err(ApiError::Duplicate(existing_id.clone()));
+            }
+        }
+
+        // Create-only, updating an existing movie is what PUT is for
+        if !state.db.insert(movie.clone()).await? {
+            return Err(ApiError::Conflict(movie.id));
+        }
+        state.movie_created(movie.clone(), &actor).await;
+        Ok(CreatedMovie {
+            movie,
+            duplicate_of,
+        })
+    };
+
+    match idempotency::idempotency_key(&headers)? {
+        Some(key) => state.idempotency.run(key, fingerprint, create).await,
+        None => create().await,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/movies",
+    params(ListParams),
+    responses(
+        (status = 200, description = "One page of movies. With `Accept: application/x-ndjson` one movie per line, and every match unless limit is set.",
+            content((Vec<Movie> = "application/json"), (Movie = "application/x-ndjson"))),
+        (status = 400, description = "year_min is greater than year_max", body = ErrorBody),
+    ),
+    tag = "movies"
+)]
+async fn list_movies(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ListParams>,
+) -> Result<Response, ApiError> {
+    let ndjson = wants_ndjson(&headers);
+    // Streaming doesn't build the whole page in memory, so it isn't paged unless asked to be
+    let limit = match params.limit {
+        Some(limit) if ndjson => limit,
+        None if ndjson => usize::MAX,
+        limit => limit.unwrap_or(DEFAULT_LIST_LIMIT).min(MAX_LIST_LIMIT),
+    };
+    let offset = params.offset.unwrap_or(0);
+    let years = year_range(params.year_min, params.year_max)?;
+    let include_deleted = params.include_deleted;
+    let matches = move |movie: &Movie| {
+        (include_deleted || !movie.is_deleted()) && years.contains(&movie.year)
+    };
+    let (sort, order) = (
+        params.sort.unwrap_or_default(),
+        params.order.unwrap_or_default(),
+    );
+
+    // The store already yields id order, so this is the one listing that doesn't need every
+    // movie in memory at once. Any other order can only be known after reading all of them.
+    if ndjson && sort == SortField::Id && order == SortOrder::Asc {
+        let movies = state.db.stream().await?.filter(move |movie| match movie {
+            Ok(movie) => matches(movie),
+            Err(_) => true,
+        });
+        return Ok(ndjson_body(movies.skip(offset).take(limit)));
+    }
+
+    let mut movies = state.db.list().await?;
+    movies.retain(matches);
+    // HashMap iteration order is unstable, sort so pages are deterministic
+    sort_movies(&mut movies, sort, order);
+
+    let page = movies.into_iter().skip(offset).take(limit);
+    if ndjson {
+        return Ok(ndjson_body(tokio_stream::iter(page.map(Ok))));
+    }
+    Ok(Json(page.collect::<Vec<_>>()).into_response())
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct MovieCount {
+    pub count: usize,
+}
+
+#[utoipa::path(
+    get,
+    path = "/movies/count",
+    responses((status = 200, description = "Number of movies", body = MovieCount)),
+    tag = "movies"
+)]
+async fn count_movies(State(state): State<AppState>) -> Result<Json<MovieCount>, ApiError> {
+    let count = state.db.count().await?;
+    Ok(Json(MovieCount { count }))
+}
+
+// Year fields are null when there are no movies
+#[derive(Serialize, Debug, Default, ToSchema)]
+struct MovieStats {
+    pub total: usize,
+    pub good_count: usize,
+    pub bad_count: usize,
+    pub earliest_year: Option<u16>,
+    pub latest_year: Option<u16>,
+    pub average_year: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/movies/stats",
+    responses((status = 200, description = "Aggregates over every movie", body = MovieStats)),
+    tag = "movies"
+)]
+async fn movie_stats(State(state): State<AppState>) -> Result<Json<MovieStats>, ApiError> {
+    let mut stats = MovieStats::default();
+    let mut year_sum: u64 = 0;
+    for movie in state.db.list().await? {
+        if movie.is_deleted() {
+            continue;
+        }
+        stats.total += 1;
+        if movie.was_good {
+            stats.good_count += 1;
+        } else {
+            stats.bad_count += 1;
+        }
+        stats.earliest_year = Some(
+            stats
+                .earliest_year
+                .map_or(movie.year, |y| y.min(movie.year)),
+        );
+        stats.latest_year = Some(stats.latest_year.map_or(movie.year, |y| y.max(movie.year)));
+        year_sum += u64::from(movie.year);
+    }
+    if stats.total > 0 {
+        stats.average_year = Some(year_sum as f64 / stats.total as f64);
+    }
+    Ok(Json(stats))
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RandomParams {
+    pub was_good: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/movies/random",
+    params(RandomParams),
+    responses(
+        (status = 200, description = "A random movie", body = Movie),
+        (status = 404, description = "No movie matched", body = ErrorBody),
+    ),
+    tag = "movies"
+)]
+async fn random_movie(
+    State(state): State<AppState>,
+    Query(params): Query<RandomParams>,
+) -> Result<Json<Movie>, ApiError> {
+    let candidates: Vec<Movie> = state
+        .db
+        .list()
+        .await?
+        .into_iter()
+        .filter(|movie| !movie.is_deleted())
+        .filter(|movie| {
+            params
+                .was_good
+                .is_none_or(|was_good| movie.was_good == was_good)
+        })
+        .collect();
+
+    candidates
+        .choose(&mut rand::rng())
+        .cloned()
+        .map(Json)
+        .ok_or(ApiError::NoMatch("no movies to pick from"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/movies/search",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Every matching movie", body = Vec<Movie>),
+        (status = 400, description = "year_min is greater than year_max", body = ErrorBody),
+    ),
+    tag = "movies"
+)]
+async fn search_movies(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<Movie>>, ApiError> {
+    let years = year_range(params.year_min, params.year_max)?;
+    let mut movies: Vec<Movie> = state
+        .db
+        .list()
+        .await?
+        .into_iter()
+        .filter(|movie| !movie.is_deleted() && params.matches(movie, &years))
+        .collect();
+    movies.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(Json(movies))
+}
+
+// Missing ids are simply left out of the response
+#[utoipa::path(
+    post,
+    path = "/movies/batch",
+    request_body(content = Vec<String>, description = "Movie ids"),
+    responses(
+        (status = 200, description = "Found movies by id, missing ids are left out", body = HashMap<String, Movie>),
+        (status = 400, description = "Too many ids", body = ErrorBody),
+    ),
+    tag = "movies"
+)]
+async fn batch_get_movies(
+    State(state): State<AppState>,
+    Json(movie_ids): Json<Vec<String>>,
+) -> Result<Json<HashMap<String, Movie>>, ApiError> {
+    if movie_ids.len() > MAX_BATCH_IDS {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_BATCH_IDS} ids can be fetched at once, got {}",
+            movie_ids.len()
+        )));
+    }
+
+    let mut movies = HashMap::with_capacity(movie_ids.len());
+    for movie_id in movie_ids {
+        let movie_id = normalize_id(&movie_id);
+        if let Some(movie) = state.load_movie(&movie_id).await? {
+            movies.insert(movie_id, movie);
+        }
+    }
+    Ok(Json(movies))
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+enum BulkStatus {
+    Created,
+    // Already existed, the stored movie was left alone
+    Conflict,
+    Invalid,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct BulkResult {
+    pub id: String,
+    pub status: BulkStatus,
+    // Only set for invalid movies
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+// Results are returned in the same order as the submitted movies
+#[utoipa::path(
+    post,
+    path = "/movies/bulk",
+    request_body = Vec<Movie>,
+    responses((status = 207, description = "Outcome per movie, in request order", body = Vec<BulkResult>)),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+async fn bulk_add_movies(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(movies): Json<Vec<Movie>>,
+) -> Result<(StatusCode, Json<Vec<BulkResult>>), ApiError> {
+    let mut results: Vec<BulkResult> = Vec::with_capacity(movies.len());
+    let mut valid = Vec::with_capacity(movies.len());
+    for mut movie in movies {
+        // New movies never start out deleted, and start at the first version like a single create
+        movie.deleted_at = None;
+        movie.version = 1;
+        movie.id = normalize_id(&movie.id);
+        let (status, errors) = match movie.validate() {
+            Ok(()) => {
+                valid.push(movie.clone());
+                // Placeholder until the store tells us whether it was created
+                (BulkStatus::Created, Vec::new())
+            }
+            Err(err) => (BulkStatus::Invalid, err.fields),
+        };
+        results.push(BulkResult {
+            id: movie.id,
+            status,
+            errors,
+        });
+    }
+
+    let created = state.db.insert_many(valid.clone()).await?;
+    let mut inserted = valid.into_iter().zip(created);
+    for result in results
+        .iter_mut()
+        .filter(|result| result.status != BulkStatus::Invalid)
     {
-        let locked_cache = state.cache.read().await;
-        if let Some(res) = locked_cache.get(&movie_id) {
-            return Ok(Json(res.clone()));
+        match inserted.next() {
+            Some((movie, true)) => state.movie_created(movie, &actor).await,
+            _ => result.status = BulkStatus::Conflict,
         }
     }
 
-    let locked_db = state.db.read().await;
-    if let Some(movie) = locked_db.get(&movie_id) {
-        // Scope for lock
-        {
-            let mut locked_cache = state.cache.write().await;
-            locked_cache.insert(movie_id, movie.clone());
+    Ok((StatusCode::MULTI_STATUS, Json(results)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/movie/{movie_id}",
+    params(
+        ("movie_id" = String, Path, description = "Case-insensitive, surrounding whitespace is ignored"),
+        ("If-Match" = Option<String>, Header, description = "Version being replaced, overrides the body's version"),
+    ),
+    request_body = ReplaceMovie,
+    responses(
+        (status = 200, description = "Replaced", headers(("etag" = String, description = "The new version"))),
+        (status = 400, description = "Path and body ids differ", body = ErrorBody),
+        (status = 404, description = "No such movie", body = ErrorBody),
+        (status = 409, description = "The movie is no longer at the expected version", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+        (status = 428, description = "Neither If-Match nor a version was sent", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+async fn update_movie(
+    State(state): State<AppState>,
+    MovieId(movie_id): MovieId,
+    actor: Actor,
+    headers: HeaderMap,
+    Json(replacement): Json<ReplaceMovie>,
+) -> Result<Response, ApiError> {
+    let Some(expected) = if_match(&headers)?.or(replacement.version) else {
+        return Err(ApiError::PreconditionRequired(
+            "send the version being replaced as If-Match or in the body",
+        ));
+    };
+    let mut movie = replacement.into_movie();
+    if movie.id != movie_id {
+        return Err(ApiError::BadRequest(format!(
+            "path id `{movie_id}` does not match body id `{}`",
+            movie.id
+        )));
+    }
+    movie.validate()?;
+
+    // Deleted movies have to be restored before they can be replaced
+    let Some(existing) = state
+        .db
+        .get(&movie_id)
+        .await?
+        .filter(|existing| !existing.is_deleted())
+    else {
+        return Err(ApiError::NotFound(movie_id));
+    };
+    movie.version = next_version(&existing, expected)?;
+    if !state.db.update(movie.clone(), expected).await? {
+        return Err(ApiError::VersionMismatch { movie_id, expected });
+    }
+
+    // Invalidate only once the db has the new value so get_movie can't re-cache the old one
+    state.invalidate(&movie_id).await;
+    state
+        .audit
+        .record(AuditAction::Update, &movie_id, &actor)
+        .await;
+    let etag = etag(&movie);
+    state.publish(MovieEvent::Updated(movie));
+    Ok(([(header::ETAG, etag)], StatusCode::OK).into_response())
+}
+
+// Only the provided fields are changed, the id can't be patched
+#[derive(Deserialize, Debug, ToSchema)]
+struct MoviePatch {
+    pub name: Option<String>,
+    pub year: Option<u16>,
+    pub was_good: Option<bool>,
+    pub genres: Option<Vec<String>>,
+    pub rating: Option<f32>,
+    // The version being patched, required unless If-Match is sent
+    pub version: Option<u64>,
+}
+
+impl MoviePatch {
+    fn apply(self, movie: &mut Movie) {
+        if let Some(name) = self.name {
+            movie.name = name;
         }
-        println!("Found Movie: {:?}", movie);
-        Ok(Json(movie.clone()))
-    } else {
-        Err(StatusCode::NOT_FOUND)
+        if let Some(year) = self.year {
+            movie.year = year;
+        }
+        if let Some(was_good) = self.was_good {
+            movie.was_good = was_good;
+        }
+        if let Some(genres) = self.genres {
+            movie.genres = genres;
+        }
+        if let Some(rating) = self.rating {
+            movie.rating = Some(rating);
+        }
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/movie/{movie_id}",
+    params(
+        ("movie_id" = String, Path, description = "Case-insensitive, surrounding whitespace is ignored"),
+        ("If-Match" = Option<String>, Header, description = "Version being patched, overrides the body's version"),
+    ),
+    request_body = MoviePatch,
+    responses(
+        (status = 200, description = "The patched movie", body = Movie),
+        (status = 404, description = "No such movie", body = ErrorBody),
+        (status = 409, description = "The movie is no longer at the expected version", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+        (status = 428, description = "Neither If-Match nor a version was sent", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+async fn patch_movie(
+    State(state): State<AppState>,
+    MovieId(movie_id): MovieId,
+    actor: Actor,
+    headers: HeaderMap,
+    Json(patch): Json<MoviePatch>,
+) -> Result<Json<Movie>, ApiError> {
+    let Some(expected) = if_match(&headers)?.or(patch.version) else {
+        return Err(ApiError::PreconditionRequired(
+            "send the version being patched as If-Match or in the body",
+        ));
+    };
+    // Straight from the db, a cached copy could be stale
+    let Some(mut movie) = state
+        .db
+        .get(&movie_id)
+        .await?
+        .filter(|movie| !movie.is_deleted())
+    else {
+        return Err(ApiError::NotFound(movie_id));
+    };
+    let version = next_version(&movie, expected)?;
+    patch.apply(&mut movie);
+    movie.validate()?;
+    movie.version = version;
+
+    if !state.db.update(movie.clone(), expected).await? {
+        return Err(ApiError::VersionMismatch { movie_id, expected });
+    }
+
+    state.invalidate(&movie_id).await;
+    state
+        .audit
+        .record(AuditAction::Update, &movie_id, &actor)
+        .await;
+    state.publish(MovieEvent::Updated(movie.clone()));
+    Ok(Json(movie))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/movie/{movie_id}",
+    params(("movie_id" = String, Path, description = "Case-insensitive, surrounding whitespace is ignored")),
+    responses(
+        (status = 204, description = "Soft-deleted, see the restore endpoint"),
+        (status = 404, description = "No such movie", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+async fn delete_movie(
+    State(state): State<AppState>,
+    MovieId(movie_id): MovieId,
+    actor: Actor,
+) -> Result<StatusCode, ApiError> {
+    // Soft delete, the movie stays in the db so it can be restored
+    let Some(movie) = state
+        .db
+        .get(&movie_id)
+        .await?
+        .filter(|movie| !movie.is_deleted())
+    else {
+        return Err(ApiError::NotFound(movie_id));
+    };
+    // No version needed, but a write that lands in between still mustn't be overwritten
+    let expected = movie.version;
+    let movie = Movie {
+        deleted_at: Some(unix_now()),
+        version: next_version(&movie, expected)?,
+        ..movie
+    };
+    // Update the db first so the cache can't resurrect the deleted record
+    if !state.db.update(movie, expected).await? {
+        return Err(ApiError::VersionMismatch { movie_id, expected });
+    }
+    state.metrics.movie_removed();
+
+    state.invalidate(&movie_id).await;
+    state
+        .audit
+        .record(AuditAction::Delete, &movie_id, &actor)
+        .await;
+    state.publish(MovieEvent::Deleted(movie_id));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Restoring a movie that isn't deleted is a no-op
+#[utoipa::path(
+    post,
+    path = "/movie/{movie_id}/restore",
+    params(("movie_id" = String, Path, description = "Case-insensitive, surrounding whitespace is ignored")),
+    responses(
+        (status = 200, description = "The restored movie", body = Movie),
+        (status = 404, description = "No such movie", body = ErrorBody),
+    ),
+    security(("api_key" = [])),
+    tag = "movies"
+)]
+async fn restore_movie(
+    State(state): State<AppState>,
+    MovieId(movie_id): MovieId,
+    actor: Actor,
+) -> Result<Json<Movie>, ApiError> {
+    let Some(movie) = state.db.get(&movie_id).await? else {
+        return Err(ApiError::NotFound(movie_id));
+    };
+    if !movie.is_deleted() {
+        return Ok(Json(movie));
+    }
+
+    let expected = movie.version;
+    let movie = Movie {
+        deleted_at: None,
+        version: next_version(&movie, expected)?,
+        ..movie
+    };
+    if !state.db.update(movie.clone(), expected).await? {
+        return Err(ApiError::VersionMismatch { movie_id, expected });
     }
+    state.metrics.movie_added();
+
+    state.invalidate(&movie_id).await;
+    state
+        .audit
+        .record(AuditAction::Restore, &movie_id, &actor)
+        .await;
+    state.publish(MovieEvent::Updated(movie.clone()));
+    Ok(Json(movie))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
 }
 
-async fn add_movie(State(state): State<AppState>, Json(movie): Json<Movie>) -> StatusCode {
-    let mut locked_db = state.db.write().await;
-    if locked_db.insert(movie.id.clone(), movie).is_some() {
-        StatusCode::ACCEPTED
+#[derive(Serialize)]
+struct HealthStatus {
+    pub status: &'static str,
+}
+
+// Liveness, the process is up and serving requests
+async fn health() -> Json<HealthStatus> {
+    Json(HealthStatus { status: "ok" })
+}
+
+// Readiness, only send traffic once startup is done
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<HealthStatus>) {
+    if state.ready.load(Ordering::Acquire) {
+        (StatusCode::OK, Json(HealthStatus { status: "ready" }))
     } else {
-        StatusCode::CREATED
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthStatus {
+                status: "not ready",
+            }),
+        )
+    }
+}
+
+// Resolves on ctrl-c or SIGTERM, axum then stops accepting and lets in-flight requests finish
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+    tracing::info!("shutting down");
+    // Stop the load balancer sending new traffic while we drain
+    state.ready.store(false, Ordering::Release);
+    state.shutdown.send_replace(true);
+}
+
+/// Every route and layer, built from `state.config`. Kept out of `main` so tests can drive the
+/// whole app without binding a socket.
+fn app(state: AppState) -> Router {
+    let config = state.config.clone();
+    let app = Router::new()
+        .nest(v1::PREFIX, v1::router(&state, config.api_key_protect_reads))
+        .merge(openapi::router(config.swagger_ui))
+        .merge(admin::router(&state))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_requests,
+        ))
+        // Operational endpoints are added after the route layers so they stay out of the metrics
+        // and any auth, and aren't versioned with the API
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .route("/metrics", get(metrics::render_metrics))
+        // Body extractors reject anything larger with a 413
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+        .with_state(state);
+    let app = match &config.allowed_origins {
+        Some(origins) => app
+            .layer(cors::cors_layer(origins))
+            .layer(axum::middleware::from_fn(cors::preflight_no_content)),
+        None => app,
+    };
+    app
+        // Only covers producing the response, streamed bodies like SSE can outlive it
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            config.request_timeout,
+        ))
+        // gzip or brotli depending on Accept-Encoding, small bodies and SSE are left alone
+        .layer(CompressionLayer::new())
+        // Logs method, path, status and latency for every request
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_id::make_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        // Outside the trace layer so the span can pick the id up. Kept from the client when
+        // sent, otherwise a new UUID, and echoed back either way.
+        .layer(PropagateRequestIdLayer::new(
+            request_id::REQUEST_ID_HEADER.clone(),
+        ))
+        .layer(SetRequestIdLayer::new(
+            request_id::REQUEST_ID_HEADER.clone(),
+            MakeRequestUuid,
+        ))
 }
 
 // Create Axum server with the following endpoints:
@@ -64,17 +1341,99 @@ async fn add_movie(State(state): State<AppState>, Json(movie): Json<Movie>) -> S
 // As a bonus: implement a caching layer so we don't need to make expensive "DB" lookups, etc.
 #[tokio::main]
 async fn main() {
-    let state = AppState {
-        db: Arc::new(RwLock::new(HashMap::default())),
-        cache: Arc::new(RwLock::new(HashMap::default())),
+    // Defaults to INFO so every request is logged, override with e.g. RUST_LOG=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let config = Config::from_env().unwrap_or_else(|err| {
+        tracing::error!("{err}");
+        std::process::exit(1);
+    });
+
+    // SQLite when DATABASE_URL is set, otherwise the in-memory db backed by a JSON file
+    let db: Arc<dyn MovieStore> = match &config.database_url {
+        Some(url) => Arc::new(
+            SqliteStore::connect(url)
+                .await
+                .unwrap_or_else(|err| panic!("unable to open database {url}: {err}")),
+        ),
+        None => Arc::new(
+            InMemoryStore::load(config.db_path.clone())
+                .await
+                .unwrap_or_else(|err| panic!("unable to open database: {err}")),
+        ),
     };
-    let app = Router::new()
-        .route("/movie/:movie_id", get(get_movie))
-        .route("/movie", post(add_movie))
-        .with_state(state);
+    let memory_cache = || Arc::new(MemoryCache::new(config.cache_capacity, config.cache_ttl));
+    let cache: Arc<dyn Cache> = match config.cache_backend {
+        CacheBackend::Memory => memory_cache(),
+        // Better a per-node cache than refusing to start, the store is the source of truth anyway
+        CacheBackend::Redis => match RedisCache::connect(&config.redis_url, config.cache_ttl).await
+        {
+            Ok(cache) => Arc::new(cache),
+            Err(err) => {
+                tracing::warn!(
+                    "unable to connect to redis at {}, using the in-memory cache: {err}",
+                    config.redis_url
+                );
+                memory_cache()
+            }
+        },
+    };
+    let mut state = AppState::new(db, cache);
+    state.api_key = config.api_key.as_deref().map(Arc::from);
+    if state.api_key.is_none() {
+        tracing::warn!("API_KEY is not set, authentication is disabled");
+    }
+    state.webhook = config
+        .webhook_url
+        .clone()
+        .map(|url| Arc::new(Webhook::new(url)));
+    if let Some(path) = &config.audit_log_path {
+        let audit = AuditLog::open(path)
+            .await
+            .unwrap_or_else(|err| panic!("unable to open audit log {}: {err}", path.display()));
+        state.audit = Arc::new(audit);
+    }
+    if config.rate_limit_per_minute > 0 {
+        state.rate_limiter = Some(Arc::new(RateLimiter::new(config.rate_limit_per_minute)));
+    }
+    match state.db.count().await {
+        Ok(count) => state.metrics.set_movies(count),
+        Err(err) => tracing::warn!("unable to count movies for metrics: {err}"),
+    }
+    if config.cache_warm_count > 0 {
+        // Before binding, so /ready only reports ready once the cache is warm. Bounded by the
+        // cache's capacity, and a failure just leaves misses to go to the db.
+        match state.warm_cache(config.cache_warm_count).await {
+            Ok(warmed) => tracing::info!("warmed cache with {warmed} movies"),
+            Err(err) => tracing::warn!("unable to warm cache: {err}"),
+        }
+    }
+    state.config = Arc::new(config.clone());
+    let app = app(state.clone());
 
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    println!("Listening on localhost:3000");
-    axum::serve(listener, app).await.unwrap();
+    // run our app with hyper
+    let listener = tokio::net::TcpListener::bind(config.addr)
+        .await
+        .unwrap_or_else(|err| panic!("unable to bind {}: {err}", config.addr));
+    tracing::info!("Listening on {}", listener.local_addr().unwrap());
+    state.ready.store(true, Ordering::Release);
+    // Connection info is needed for per-IP rate limiting
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(state.clone()))
+    .await
+    .unwrap();
+
+    if let Err(err) = state.db.flush().await {
+        tracing::error!("failed to flush db on shutdown: {err}");
+    }
+    if let Err(err) = state.audit.flush().await {
+        tracing::error!("failed to flush audit log on shutdown: {err}");
+    }
 }
@@ -0,0 +1,110 @@
+use crate::AppState;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Hand-rolled counters rendered in the Prometheus text format at `GET /metrics`
+#[derive(Default)]
+pub struct Metrics {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    movies: AtomicI64,
+    // (method, route, status), a BTreeMap keeps the rendered output stable
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+}
+
+impl Metrics {
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_movies(&self, count: usize) {
+        self.movies.store(count as i64, Ordering::Relaxed);
+    }
+
+    pub fn movie_added(&self) {
+        self.movies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn movie_removed(&self) {
+        self.movies.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn record_request(&self, method: String, route: String, status: u16) {
+        let mut requests = self.requests.lock().unwrap();
+        *requests.entry((method, route, status)).or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = writeln!(
+            out,
+            "# HELP movies_cache_hits_total Cache lookups served from the cache"
+        );
+        let _ = writeln!(out, "# TYPE movies_cache_hits_total counter");
+        let _ = writeln!(
+            out,
+            "movies_cache_hits_total {}",
+            self.cache_hits.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP movies_cache_misses_total Cache lookups that fell through to the db"
+        );
+        let _ = writeln!(out, "# TYPE movies_cache_misses_total counter");
+        let _ = writeln!(
+            out,
+            "movies_cache_misses_total {}",
+            self.cache_misses.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "# HELP movies_total Movies currently stored");
+        let _ = writeln!(out, "# TYPE movies_total gauge");
+        let _ = writeln!(out, "movies_total {}", self.movies.load(Ordering::Relaxed));
+        let _ = writeln!(
+            out,
+            "# HELP http_requests_total Requests handled per endpoint"
+        );
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{method}\",path=\"{route}\",status=\"{status}\"}} {count}"
+            );
+        }
+        out
+    }
+}
+
+/// Counts requests by their route template (e.g. `/movie/:movie_id`) so ids don't blow up the
+/// label cardinality. Must be added with `route_layer` so the matched path is known.
+pub async fn track_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+
+    let response = next.run(req).await;
+    state
+        .metrics
+        .record_request(method, route, response.status().as_u16());
+    response
+}
+
+pub async fn render_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
@@ -0,0 +1,66 @@
+use crate::auth::API_KEY_HEADER;
+use crate::extract::Json;
+use crate::v1;
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+pub const OPENAPI_PATH: &str = "/api-docs/openapi.json";
+
+/// The whole API, each version's own `ApiDoc` is nested under its prefix
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Movies API"),
+    nest((path = "/v1", api = v1::ApiDoc)),
+    modifiers(&ApiKeyAuth),
+)]
+struct ApiDoc;
+
+// Writes list `api_key` as their security requirement, this defines what that is
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER.as_str()))),
+        );
+    }
+}
+
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Loads the UI from a CDN rather than bundling it into the binary
+async fn swagger_ui() -> Html<String> {
+    Html(format!(
+        r##"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Movies API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({{ url: "{OPENAPI_PATH}", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##
+    ))
+}
+
+/// The spec, plus Swagger UI when `with_ui` is set
+pub fn router<S: Clone + Send + Sync + 'static>(with_ui: bool) -> Router<S> {
+    let docs = Router::new().route(OPENAPI_PATH, get(openapi_json));
+    if with_ui {
+        docs.route("/swagger-ui", get(swagger_ui))
+    } else {
+        docs
+    }
+}
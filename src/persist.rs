@@ -0,0 +1,50 @@
+use crate::Movie;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_DB_PATH: &str = "movies.json";
+
+/// Loads the db from `path`, starting empty if the file is missing. A file that can't be parsed
+/// is moved aside first (see `corrupt_path`) so the next save can't overwrite what's left of it.
+pub async fn load(path: &Path) -> io::Result<HashMap<String, Movie>> {
+    let contents = match tokio::fs::read(path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::default()),
+        Err(err) => return Err(err),
+    };
+
+    match serde_json::from_slice(&contents) {
+        Ok(db) => Ok(db),
+        Err(err) => {
+            let aside = corrupt_path(path);
+            tokio::fs::rename(path, &aside).await?;
+            tracing::warn!(
+                "corrupt db file {}: {err}, moved to {} and starting empty",
+                path.display(),
+                aside.display()
+            );
+            Ok(HashMap::default())
+        }
+    }
+}
+
+// e.g. movies.json.corrupt-1700000000
+fn corrupt_path(path: &Path) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".corrupt-{now}"));
+    aside.into()
+}
+
+/// Writes the whole db to `path`. Goes through a temp file so a crash mid write can't leave
+/// a truncated file behind.
+pub async fn save(path: &Path, db: &HashMap<String, Movie>) -> io::Result<()> {
+    let contents = serde_json::to_vec_pretty(db)?;
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, contents).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
@@ -0,0 +1,97 @@
+use crate::error::ApiError;
+use crate::AppState;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use lru::LruCache;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Past this many tracked clients the least recently seen one is forgotten, which only resets
+// its bucket to full
+const MAX_TRACKED_CLIENTS: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket per client IP, allowing `per_minute` requests with bursts of the same size
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<LruCache<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            per_minute,
+            buckets: Mutex::new(LruCache::new(MAX_TRACKED_CLIENTS)),
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        f64::from(self.per_minute)
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity() / 60.0
+    }
+
+    /// Takes a token for `ip`, or returns how long until one is available
+    async fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut locked_buckets = self.buckets.lock().await;
+        let bucket = locked_buckets.get_or_insert_mut(ip, || Bucket {
+            tokens: self.capacity(),
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec()).min(self.capacity());
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_per_sec(),
+            ))
+        }
+    }
+}
+
+// The peer address, or the first X-Forwarded-For entry when a trusted proxy sets it. Clients
+// can send any X-Forwarded-For they like, so it's ignored unless `trust_forwarded_for`.
+fn client_ip(req: &Request, trust_forwarded_for: bool) -> Option<IpAddr> {
+    let forwarded = req
+        .headers()
+        .get("x-forwarded-for")
+        .filter(|_| trust_forwarded_for)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+    forwarded.or_else(|| {
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    })
+}
+
+pub async fn rate_limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let ip = client_ip(&req, state.config.trust_forwarded_for);
+    let (Some(limiter), Some(ip)) = (&state.rate_limiter, ip) else {
+        return next.run(req).await;
+    };
+
+    match limiter.check(ip).await {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => ApiError::TooManyRequests {
+            retry_after_secs: retry_after.as_secs_f64().ceil() as u64,
+        }
+        .into_response(),
+    }
+}
@@ -0,0 +1,22 @@
+use axum::body::Body;
+use axum::http::{HeaderName, Request};
+use tracing::Span;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Same fields as tower-http's default span plus the request id, so every log line for a
+/// request can be correlated. The id is always set by the time this runs.
+pub fn make_span(req: &Request<Body>) -> Span {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+        request_id = %request_id,
+    )
+}
@@ -0,0 +1,264 @@
+use super::{unused_id, MovieStore, StoreError};
+use crate::{persist, Movie};
+use async_trait::async_trait;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+/// The original HashMap "db", optionally written to a JSON file after every mutation
+#[derive(Default)]
+pub struct InMemoryStore {
+    // Sharded, so a write only blocks readers of the same shard rather than the whole db
+    movies: DashMap<String, Movie>,
+    path: Option<PathBuf>,
+    // Saves run one at a time so an older snapshot can't overwrite a newer one
+    save_lock: Mutex<()>,
+}
+
+impl InMemoryStore {
+    /// Loads any movies already saved at `path` and persists back to it from then on. Ids saved
+    /// before they were normalized are rewritten, see `normalize_ids`.
+    pub async fn load(path: PathBuf) -> Result<Self, StoreError> {
+        let saved = persist::load(&path)
+            .await
+            .map_err(|err| StoreError(format!("unable to load {}: {err}", path.display())))?;
+        let (movies, renamed) = normalize_ids(saved);
+        let store = InMemoryStore {
+            movies,
+            path: Some(path),
+            save_lock: Mutex::default(),
+        };
+        if renamed {
+            store.save().await?;
+        }
+        Ok(store)
+    }
+
+    // The snapshot is taken once the save lock is held, so it includes every write before it. On
+    // failure the write stays in memory and goes out with the next successful save.
+    async fn save(&self) -> Result<(), StoreError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _locked_save = self.save_lock.lock().await;
+        let snapshot: HashMap<String, Movie> = self
+            .movies
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        persist::save(path, &snapshot)
+            .await
+            .map_err(|err| StoreError(format!("failed to persist db to {}: {err}", path.display())))
+    }
+}
+
+// Files saved before ids were normalized can have keys no lookup reaches anymore. A movie already
+// stored under the normalized id keeps it, a legacy spelling of it is renamed (see `unused_id`).
+fn normalize_ids(saved: HashMap<String, Movie>) -> (DashMap<String, Movie>, bool) {
+    let (normalized, mut legacy): (Vec<_>, Vec<_>) = saved
+        .into_iter()
+        .partition(|(id, movie)| *id == crate::normalize_id(id) && movie.id == *id);
+    let renamed = !legacy.is_empty();
+    // So which legacy spelling keeps the plain id doesn't depend on HashMap order
+    legacy.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let movies: DashMap<String, Movie> = normalized.into_iter().collect();
+    for (legacy_id, mut movie) in legacy {
+        movie.id = unused_id(&legacy_id, |id| movies.contains_key(id));
+        movies.insert(movie.id.clone(), movie);
+    }
+    (movies, renamed)
+}
+
+#[async_trait]
+impl MovieStore for InMemoryStore {
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StoreError> {
+        Ok(self.movies.get(id).map(|movie| movie.clone()))
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<bool, StoreError> {
+        // The entry guard is dropped at the end of the match, never held across the save
+        match self.movies.entry(movie.id.clone()) {
+            Entry::Occupied(_) => return Ok(false),
+            Entry::Vacant(slot) => {
+                slot.insert(movie);
+            }
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    async fn insert_many(&self, movies: Vec<Movie>) -> Result<Vec<bool>, StoreError> {
+        let created: Vec<bool> = movies
+            .into_iter()
+            .map(|movie| match self.movies.entry(movie.id.clone()) {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(slot) => {
+                    slot.insert(movie);
+                    true
+                }
+            })
+            .collect();
+        // One save for the whole batch
+        if created.contains(&true) {
+            self.save().await?;
+        }
+        Ok(created)
+    }
+
+    async fn update(&self, movie: Movie, expected_version: u64) -> Result<bool, StoreError> {
+        // The entry stays locked between the check and the write
+        match self.movies.get_mut(&movie.id) {
+            Some(mut existing) if existing.version == expected_version => *existing = movie,
+            _ => return Ok(false),
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StoreError> {
+        Ok(self.movies.iter().map(|movie| movie.clone()).collect())
+    }
+
+    async fn count(&self) -> Result<usize, StoreError> {
+        Ok(self
+            .movies
+            .iter()
+            .filter(|movie| !movie.is_deleted())
+            .count())
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        self.save().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn movie(id: String) -> Movie {
+        Movie {
+            id,
+            name: "Heat".to_string(),
+            year: 1995,
+            was_good: true,
+            genres: Vec::new(),
+            rating: None,
+            deleted_at: None,
+            version: 1,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers_and_readers_see_every_insert() {
+        const WRITERS: usize = 8;
+        const PER_WRITER: usize = 250;
+        let store = Arc::new(InMemoryStore::default());
+
+        let mut tasks = Vec::new();
+        for writer in 0..WRITERS {
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..PER_WRITER {
+                    let id = format!("{writer}-{i}");
+                    assert!(store.insert(movie(id.clone())).await.unwrap());
+                    // Reads interleave with other writers' inserts on every shard
+                    assert!(store.get(&id).await.unwrap().is_some());
+                }
+            }));
+        }
+        // Every writer also races the others for one shared id, exactly one can win it
+        let contested = (0..WRITERS).map(|_| {
+            let store = store.clone();
+            tokio::spawn(async move { store.insert(movie("shared".to_string())).await.unwrap() })
+        });
+        let contested: Vec<_> = contested.collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let mut winners = 0;
+        for task in contested {
+            winners += usize::from(task.await.unwrap());
+        }
+
+        assert_eq!(winners, 1);
+        assert_eq!(store.count().await.unwrap(), WRITERS * PER_WRITER + 1);
+    }
+
+    // Unique per test so parallel runs don't share a file
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("movies-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn movies_survive_a_reload() {
+        let path = temp_path();
+        let store = InMemoryStore::load(path.clone()).await.unwrap();
+        assert!(store.insert(movie("heat".to_string())).await.unwrap());
+
+        let reloaded = InMemoryStore::load(path.clone()).await.unwrap();
+        let found = reloaded.get("heat").await.unwrap();
+        assert_eq!(found.map(|movie| movie.name), Some("Heat".to_string()));
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn legacy_ids_are_normalized_on_load() {
+        let path = temp_path();
+        let saved = HashMap::from([
+            ("TT0111161".to_string(), movie("TT0111161".to_string())),
+            (" Heat".to_string(), movie(" Heat".to_string())),
+            ("heat".to_string(), movie("heat".to_string())),
+        ]);
+        persist::save(&path, &saved).await.unwrap();
+
+        let store = InMemoryStore::load(path.clone()).await.unwrap();
+        let found = store.get("tt0111161").await.unwrap();
+        assert_eq!(found.map(|movie| movie.id), Some("tt0111161".to_string()));
+        // Colliding with the already normalized `heat`, so kept under a suffix
+        let renamed = store.get("heat-2").await.unwrap();
+        assert_eq!(renamed.map(|movie| movie.id), Some("heat-2".to_string()));
+        assert_eq!(store.count().await.unwrap(), 3);
+        // Rewritten, so the next load has nothing left to normalize
+        let mut keys: Vec<_> = persist::load(&path).await.unwrap().into_keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["heat", "heat-2", "tt0111161"]);
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn corrupt_file_is_moved_aside_before_starting_empty() {
+        let path = temp_path();
+        tokio::fs::write(&path, b"{not json").await.unwrap();
+
+        let store = InMemoryStore::load(path.clone()).await.unwrap();
+        assert_eq!(store.count().await.unwrap(), 0);
+        assert!(store.insert(movie("heat".to_string())).await.unwrap());
+
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        let mut entries = tokio::fs::read_dir(path.parent().unwrap()).await.unwrap();
+        let mut aside = None;
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            let name = entry.file_name().into_string().unwrap();
+            if name.starts_with(&format!("{file_name}.corrupt-")) {
+                aside = Some(entry.path());
+            }
+        }
+        let aside = aside.expect("corrupt file wasn't kept");
+        assert_eq!(tokio::fs::read(&aside).await.unwrap(), b"{not json");
+        tokio::fs::remove_file(aside).await.unwrap();
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_saves_are_reported_to_the_writer() {
+        let path = temp_path().join("missing-dir").join("movies.json");
+        let store = InMemoryStore::load(path).await.unwrap();
+        assert!(store.insert(movie("heat".to_string())).await.is_err());
+    }
+}
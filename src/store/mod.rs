@@ -0,0 +1,120 @@
+mod memory;
+mod sqlite;
+
+pub use memory::InMemoryStore;
+pub use sqlite::SqliteStore;
+
+use crate::Movie;
+use async_trait::async_trait;
+use std::fmt;
+use std::pin::Pin;
+use tokio_stream::Stream;
+
+/// Movies yielded one at a time, see `MovieStore::stream`
+pub type MovieStream = Pin<Box<dyn Stream<Item = Result<Movie, StoreError>> + Send>>;
+
+/// Storage backend for movies, handlers only talk to the db through this
+#[async_trait]
+pub trait MovieStore: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StoreError>;
+
+    /// Create-only, returns `false` without touching the stored movie if the id is taken
+    async fn insert(&self, movie: Movie) -> Result<bool, StoreError>;
+
+    /// Inserts every movie as one batch, returning whether each one was created (see `insert`)
+    async fn insert_many(&self, movies: Vec<Movie>) -> Result<Vec<bool>, StoreError> {
+        let mut created = Vec::with_capacity(movies.len());
+        for movie in movies {
+            created.push(self.insert(movie).await?);
+        }
+        Ok(created)
+    }
+
+    /// Replaces an existing movie if it's still at `expected_version`, returns `false` if there
+    /// was nothing to replace or it has been written since. `movie.version` is stored as given.
+    async fn update(&self, movie: Movie, expected_version: u64) -> Result<bool, StoreError>;
+
+    /// A movie that isn't soft-deleted with this year and the same name once trimmed and
+    /// lowercased, used to spot the same film being added under a second id
+    async fn find_by_name_year(&self, name: &str, year: u16) -> Result<Option<Movie>, StoreError> {
+        let name = crate::normalize_name(name);
+        let movies = self.list().await?;
+        Ok(movies.into_iter().find(|movie| {
+            !movie.is_deleted() && movie.year == year && crate::normalize_name(&movie.name) == name
+        }))
+    }
+
+    /// Every stored movie, in no particular order
+    async fn list(&self) -> Result<Vec<Movie>, StoreError>;
+
+    /// Every stored movie in id order, soft-deleted ones included. Backends that can read
+    /// incrementally yield movies as they're read, this default collects `list` first.
+    async fn stream(&self) -> Result<MovieStream, StoreError> {
+        let mut movies = self.list().await?;
+        movies.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Box::pin(tokio_stream::iter(movies.into_iter().map(Ok))))
+    }
+
+    /// Only counts movies that haven't been soft-deleted
+    async fn count(&self) -> Result<usize, StoreError> {
+        let movies = self.list().await?;
+        Ok(movies.iter().filter(|movie| !movie.is_deleted()).count())
+    }
+
+    /// Makes sure everything is durably written, called once on shutdown
+    async fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+// Where a movie stored before ids were normalized goes: its normalized id, or the first of
+// `heat-2`, `heat-3`, ... that's free if another movie already has that. Never drops one.
+fn unused_id(legacy_id: &str, taken: impl Fn(&str) -> bool) -> String {
+    let id = crate::normalize_id(legacy_id);
+    if !taken(&id) {
+        return id;
+    }
+    let renamed = (2u64..)
+        .map(|n| format!("{id}-{n}"))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free");
+    tracing::warn!(
+        "movie {legacy_id:?} normalizes to {id:?}, which is taken, stored as {renamed:?} instead"
+    );
+    renamed
+}
+
+#[derive(Debug)]
+pub struct StoreError(String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        StoreError(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(err: serde_json::Error) -> Self {
+        StoreError(err.to_string())
+    }
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(err: sqlx::Error) -> Self {
+        StoreError(err.to_string())
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StoreError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        StoreError(err.to_string())
+    }
+}
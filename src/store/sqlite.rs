@@ -0,0 +1,388 @@
+use super::{unused_id, MovieStore, MovieStream, StoreError};
+use crate::Movie;
+use async_trait::async_trait;
+use sqlx::query::Query;
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqlitePool, SqliteRow};
+use sqlx::{Row, Sqlite};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio_stream::{Stream, StreamExt};
+
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Connects to `url` (e.g. `sqlite://movies.db`), creating the file and running any pending
+    /// migrations. Ids stored before they were normalized are rewritten, see `normalize_ids`.
+    pub async fn connect(url: &str) -> Result<Self, StoreError> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        normalize_ids(&pool).await?;
+        Ok(SqliteStore { pool })
+    }
+}
+
+// Rows stored before ids were normalized can't be reached by a lookup. Done here rather than in a
+// migration since SQLite's lower() only handles ASCII and trim() only spaces, unlike
+// `normalize_id`. A row already under the normalized id keeps it, see `unused_id`.
+async fn normalize_ids(pool: &SqlitePool) -> Result<(), StoreError> {
+    let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM movies ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    let mut taken: HashSet<String> = ids.iter().cloned().collect();
+    let legacy: Vec<&String> = ids
+        .iter()
+        .filter(|id| crate::normalize_id(id) != **id)
+        .collect();
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    for legacy_id in legacy {
+        let id = unused_id(legacy_id, |id| taken.contains(id));
+        sqlx::query("UPDATE movies SET id = ? WHERE id = ?")
+            .bind(&id)
+            .bind(legacy_id)
+            .execute(&mut *tx)
+            .await?;
+        taken.remove(legacy_id);
+        taken.insert(id);
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+const COLUMNS: &str = "id, name, year, was_good, genres, rating, deleted_at, version";
+
+// Rows read ahead of a slow consumer of `stream`
+const STREAM_BUFFER: usize = 64;
+
+// A consumer of `stream` that takes no row for this long loses it, so a stalled client can't hold
+// a pool connection, and the read lock that blocks writers, indefinitely
+const STREAM_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Genres are stored as a JSON array
+fn movie_from_row(row: &SqliteRow) -> Result<Movie, StoreError> {
+    let year: i64 = row.try_get("year")?;
+    let genres: String = row.try_get("genres")?;
+    let deleted_at: Option<i64> = row.try_get("deleted_at")?;
+    let version: i64 = row.try_get("version")?;
+    Ok(Movie {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        year: u16::try_from(year).map_err(|_| StoreError(format!("invalid year {year}")))?,
+        was_good: row.try_get("was_good")?,
+        genres: serde_json::from_str(&genres)?,
+        rating: row.try_get("rating")?,
+        deleted_at: deleted_at
+            .map(u64::try_from)
+            .transpose()
+            .map_err(|_| StoreError(format!("invalid deleted_at {deleted_at:?}")))?,
+        version: u64::try_from(version)
+            .map_err(|_| StoreError(format!("invalid version {version}")))?,
+    })
+}
+
+fn genres_json(movie: &Movie) -> Result<String, StoreError> {
+    Ok(serde_json::to_string(&movie.genres)?)
+}
+
+// SQLite integers are signed, unix seconds won't overflow that any time soon
+fn deleted_at(movie: &Movie) -> Option<i64> {
+    movie.deleted_at.and_then(|at| i64::try_from(at).ok())
+}
+
+// Same for versions, one per write
+fn sql_version(version: u64) -> i64 {
+    i64::try_from(version).unwrap_or(i64::MAX)
+}
+
+fn insert_query(movie: &Movie) -> Result<Query<'_, Sqlite, SqliteArguments<'_>>, StoreError> {
+    Ok(sqlx::query(
+        "INSERT INTO movies (id, name, year, was_good, genres, rating, deleted_at, version) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT (id) DO NOTHING",
+    )
+    .bind(&movie.id)
+    .bind(&movie.name)
+    .bind(movie.year)
+    .bind(movie.was_good)
+    .bind(genres_json(movie)?)
+    .bind(movie.rating)
+    .bind(deleted_at(movie))
+    .bind(sql_version(movie.version)))
+}
+
+#[async_trait]
+impl MovieStore for SqliteStore {
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StoreError> {
+        let row = sqlx::query(&format!("SELECT {COLUMNS} FROM movies WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(movie_from_row).transpose()
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<bool, StoreError> {
+        let result = insert_query(&movie)?.execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_many(&self, movies: Vec<Movie>) -> Result<Vec<bool>, StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let mut created = Vec::with_capacity(movies.len());
+        for movie in &movies {
+            let result = insert_query(movie)?.execute(&mut *tx).await?;
+            created.push(result.rows_affected() > 0);
+        }
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    async fn update(&self, movie: Movie, expected_version: u64) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "UPDATE movies SET name = ?, year = ?, was_good = ?, genres = ?, rating = ?, \
+             deleted_at = ?, version = ? WHERE id = ? AND version = ?",
+        )
+        .bind(&movie.name)
+        .bind(movie.year)
+        .bind(movie.was_good)
+        .bind(genres_json(&movie)?)
+        .bind(movie.rating)
+        .bind(deleted_at(&movie))
+        .bind(sql_version(movie.version))
+        .bind(&movie.id)
+        .bind(sql_version(expected_version))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StoreError> {
+        let rows = sqlx::query(&format!("SELECT {COLUMNS} FROM movies"))
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(movie_from_row).collect()
+    }
+
+    async fn stream(&self) -> Result<MovieStream, StoreError> {
+        Ok(Box::pin(stream_rows(
+            self.pool.clone(),
+            STREAM_STALL_TIMEOUT,
+        )))
+    }
+
+    // Narrowed down by year in SQL, SQLite's lower() only handles ASCII so names are compared here
+    async fn find_by_name_year(&self, name: &str, year: u16) -> Result<Option<Movie>, StoreError> {
+        let name = crate::normalize_name(name);
+        let rows = sqlx::query(&format!(
+            "SELECT {COLUMNS} FROM movies WHERE year = ? AND deleted_at IS NULL"
+        ))
+        .bind(year)
+        .fetch_all(&self.pool)
+        .await?;
+        for row in &rows {
+            let movie = movie_from_row(row)?;
+            if crate::normalize_name(&movie.name) == name {
+                return Ok(Some(movie));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn count(&self) -> Result<usize, StoreError> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM movies WHERE deleted_at IS NULL")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as usize)
+    }
+}
+
+// Read on its own task since the cursor borrows the pool. Holds one connection until the consumer
+// has read every row, dropped the stream, or stalled for `stall_timeout`.
+fn stream_rows(pool: SqlitePool, stall_timeout: Duration) -> RowStream {
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    let complete = Arc::new(AtomicBool::new(false));
+    let read_every_row = complete.clone();
+    tokio::spawn(async move {
+        let sql = format!("SELECT {COLUMNS} FROM movies ORDER BY id");
+        let mut rows = sqlx::query(&sql).fetch(&pool);
+        while let Some(row) = rows.next().await {
+            let movie = row
+                .map_err(StoreError::from)
+                .and_then(|row| movie_from_row(&row));
+            let failed = movie.is_err();
+            match tx.send_timeout(movie, stall_timeout).await {
+                Ok(()) if !failed => {}
+                Err(SendTimeoutError::Timeout(_)) => {
+                    tracing::warn!(
+                        "gave up streaming movies to a consumer stalled for {stall_timeout:?}"
+                    );
+                    return;
+                }
+                _ => return,
+            }
+        }
+        read_every_row.store(true, Ordering::Release);
+    });
+    RowStream {
+        rows: rx,
+        complete,
+        done: false,
+    }
+}
+
+// What `stream_rows` sent, ending in an error when the reader gave up before the last row so a
+// truncated stream can't pass for a complete one
+struct RowStream {
+    rows: mpsc::Receiver<Result<Movie, StoreError>>,
+    complete: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl Stream for RowStream {
+    type Item = Result<Movie, StoreError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match ready!(this.rows.poll_recv(cx)) {
+            Some(Ok(movie)) => Poll::Ready(Some(Ok(movie))),
+            Some(Err(err)) => {
+                this.done = true;
+                Poll::Ready(Some(Err(err)))
+            }
+            None => {
+                this.done = true;
+                if this.complete.load(Ordering::Acquire) {
+                    Poll::Ready(None)
+                } else {
+                    let err = StoreError("stopped streaming movies before the last one".into());
+                    Poll::Ready(Some(Err(err)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie(id: &str, deleted_at: Option<u64>) -> Movie {
+        Movie {
+            id: id.to_string(),
+            name: "Heat".to_string(),
+            year: 1995,
+            was_good: true,
+            genres: vec!["crime".to_string()],
+            rating: Some(8.3),
+            deleted_at,
+            version: 1,
+        }
+    }
+
+    // Unique per test so parallel runs don't share a db
+    fn temp_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("movies-{}.db", uuid::Uuid::new_v4()))
+    }
+
+    async fn connect(path: &std::path::Path) -> SqliteStore {
+        SqliteStore::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn legacy_ids_are_normalized_on_connect() {
+        let path = temp_path();
+        let store = connect(&path).await;
+        // Straight through the store, which stores ids as given
+        let legacy = vec![
+            movie("heat", None),
+            movie("HEAT", None),
+            movie("Élan\t", None),
+            movie("TT0111161", None),
+        ];
+        store.insert_many(legacy).await.unwrap();
+        store.pool.close().await;
+
+        let store = connect(&path).await;
+        let mut ids: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["heat", "heat-2", "tt0111161", "élan"]);
+        assert!(store.get("élan").await.unwrap().is_some());
+
+        store.pool.close().await;
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_stalled_stream_is_cut_off_with_an_error() {
+        let path = temp_path();
+        let store = connect(&path).await;
+        // More than fit in the channel, so the reader has to wait on the consumer
+        let movies = (0..STREAM_BUFFER * 2)
+            .map(|i| movie(&format!("movie-{i:03}"), None))
+            .collect();
+        store.insert_many(movies).await.unwrap();
+
+        let mut rows = stream_rows(store.pool.clone(), Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut streamed = Vec::new();
+        while let Some(row) = rows.next().await {
+            streamed.push(row);
+        }
+        assert!(streamed.len() < STREAM_BUFFER * 2);
+        assert!(streamed.last().unwrap().is_err());
+        // Nothing is held once it gave up, so writes still go through
+        assert!(store.insert(movie("late", None)).await.unwrap());
+
+        store.pool.close().await;
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stream_yields_every_row_in_id_order() {
+        let path = temp_path();
+        let store = connect(&path).await;
+        let movies = vec![
+            movie("up", None),
+            movie("alien", Some(1_700_000_000)),
+            movie("heat", None),
+        ];
+        store.insert_many(movies).await.unwrap();
+
+        let streamed: Vec<Movie> = store
+            .stream()
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let ids: Vec<&str> = streamed.iter().map(|movie| movie.id.as_str()).collect();
+        assert_eq!(ids, ["alien", "heat", "up"]);
+        assert_eq!(streamed[0].deleted_at, Some(1_700_000_000));
+        assert_eq!(streamed[1].genres, ["crime"]);
+
+        store.pool.close().await;
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
@@ -0,0 +1,1381 @@
+use crate::auth::API_KEY_HEADER;
+use crate::cache::MemoryCache;
+use crate::config::Config;
+use crate::rate_limit::RateLimiter;
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::store::{InMemoryStore, MovieStore, StoreError};
+use crate::webhook::Webhook;
+use crate::{app, AppState, Movie};
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderValue, Method, Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+// Nothing persisted and a default config, so auth and rate limiting are off
+fn test_state() -> AppState {
+    let cache = MemoryCache::new(NonZeroUsize::new(100).unwrap(), Duration::from_secs(60));
+    AppState::new(Arc::new(InMemoryStore::default()), Arc::new(cache))
+}
+
+// JSON body when there is one, extra headers can be added before sending it with `oneshot`
+fn request(method: Method, uri: &str, body: Option<Value>) -> Request<Body> {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    request.unwrap()
+}
+
+async fn oneshot(app: &Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> Response {
+    oneshot(app, request(method, uri, body)).await
+}
+
+async fn json_body(response: Response) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn text_body(response: Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+// Reads a counter back out of the Prometheus output
+fn metric(state: &AppState, name: &str) -> u64 {
+    let rendered = state.metrics.render();
+    rendered
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("no `{name}` in metrics"))
+}
+
+fn heat() -> Value {
+    json!({"id": "heat", "name": "Heat", "year": 1995, "was_good": true})
+}
+
+fn movie_json(id: &str, name: &str, year: u16, was_good: bool) -> Value {
+    json!({"id": id, "name": name, "year": year, "was_good": was_good})
+}
+
+async fn seed(app: &Router, movies: impl IntoIterator<Item = Value>) {
+    for movie in movies {
+        let response = send(app, Method::POST, "/v1/movie", Some(movie)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
+
+// Ids of a JSON array of movies, in response order
+async fn ids(response: Response) -> Vec<String> {
+    let body = json_body(response).await;
+    body.as_array()
+        .unwrap()
+        .iter()
+        .map(|movie| movie["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn post_then_get_round_trips() {
+    let app = app(test_state());
+
+    let created = send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    assert_eq!(created.status(), StatusCode::CREATED);
+    assert_eq!(created.headers()[header::LOCATION], "/v1/movie/heat");
+
+    let fetched = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(fetched.status(), StatusCode::OK);
+    let movie: Movie = serde_json::from_value(json_body(fetched).await).unwrap();
+    assert_eq!(movie.name, "Heat");
+    assert_eq!(movie.year, 1995);
+    assert!(movie.was_good);
+}
+
+#[tokio::test]
+async fn get_missing_movie_is_not_found() {
+    let app = app(test_state());
+
+    let response = send(&app, Method::GET, "/v1/movie/nope", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(response).await["code"], "NOT_FOUND");
+}
+
+#[tokio::test]
+async fn second_get_is_served_from_the_cache() {
+    let state = test_state();
+    let app = app(state.clone());
+    send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+
+    // Creating invalidates, so the first read misses and fills the cache
+    send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(state.cache.keys().await, ["heat"]);
+    assert_eq!(metric(&state, "movies_cache_hits_total"), 0);
+
+    let response = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(metric(&state, "movies_cache_hits_total"), 1);
+    assert_eq!(metric(&state, "movies_cache_misses_total"), 1);
+}
+
+#[tokio::test]
+async fn mutations_are_audited_in_order() {
+    let app = app(test_state());
+    send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    send(&app, Method::DELETE, "/v1/movie/heat", None).await;
+
+    let response = send(&app, Method::GET, "/admin/audit", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries = json_body(response).await;
+    let actions: Vec<_> = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| (entry["action"].clone(), entry["movie_id"].clone()))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            (json!("create"), json!("heat")),
+            (json!("delete"), json!("heat"))
+        ]
+    );
+    assert_eq!(entries[0]["actor"], "anonymous");
+}
+
+// Holds on to what it read for a moment before returning it, so writes land while reads are
+// still in flight. Random so reads and writes don't fall into lockstep.
+#[derive(Default)]
+struct SlowReads(InMemoryStore);
+
+#[async_trait]
+impl MovieStore for SlowReads {
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StoreError> {
+        let movie = self.0.get(id).await;
+        tokio::time::sleep(Duration::from_micros(rand::random_range(0..2000))).await;
+        movie
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<bool, StoreError> {
+        self.0.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie, expected_version: u64) -> Result<bool, StoreError> {
+        self.0.update(movie, expected_version).await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StoreError> {
+        self.0.list().await
+    }
+}
+
+// Readers racing a writer for one id, the cache must never be left holding an older movie than
+// the db
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes_never_leave_the_cache_stale() {
+    let cache = MemoryCache::new(NonZeroUsize::new(100).unwrap(), Duration::from_secs(60));
+    let state = AppState::new(Arc::new(SlowReads::default()), Arc::new(cache));
+    let app = app(state.clone());
+    send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+
+    for round in 0..20 {
+        let writing = Arc::new(AtomicBool::new(true));
+        // Keep reading until the last write, so some reads always overlap it
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let app = app.clone();
+                let writing = writing.clone();
+                tokio::spawn(async move {
+                    while writing.load(Ordering::SeqCst) {
+                        send(&app, Method::GET, "/v1/movie/heat", None).await;
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..5 {
+            let version = state.db.get("heat").await.unwrap().unwrap().version;
+            let rating = f64::from(round * 5 + i) / 100.0;
+            let patch = json!({"rating": rating, "version": version});
+            let response = send(&app, Method::PATCH, "/v1/movie/heat", Some(patch)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        writing.store(false, Ordering::SeqCst);
+        for reader in readers {
+            reader.await.unwrap();
+        }
+
+        let stored = state.db.get("heat").await.unwrap().unwrap();
+        if let Some(cached) = state.cache.get("heat").await {
+            assert_eq!(
+                cached.rating, stored.rating,
+                "stale cache after round {round}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn invalidating_a_tag_drops_every_movie_carrying_it() {
+    let state = test_state();
+    let app = app(state.clone());
+    for (id, genres) in [("up", ["Pixar"]), ("coco", ["pixar"]), ("heat", ["Crime"])] {
+        let movie = json!({"id": id, "name": id, "year": 2009, "was_good": true, "genres": genres});
+        send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+        send(&app, Method::GET, &format!("/v1/movie/{id}"), None).await;
+    }
+
+    let tag = json!({"tag": "Pixar"});
+    let response = send(&app, Method::POST, "/admin/cache/invalidate", Some(tag)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["invalidated"], 2);
+    assert_eq!(state.cache.keys().await, ["heat"]);
+}
+
+// Ids of an NDJSON listing, in response order
+async fn ndjson_ids(app: &Router, uri: &str) -> Vec<String> {
+    let mut request = request(Method::GET, uri, None);
+    request.headers_mut().insert(
+        header::ACCEPT,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    let response = oneshot(app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/x-ndjson"
+    );
+    text_body(response)
+        .await
+        .lines()
+        .map(|line| serde_json::from_str::<Movie>(line).unwrap().id)
+        .collect()
+}
+
+#[tokio::test]
+async fn list_streams_ndjson_when_asked() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [
+            movie_json("up", "Up", 2009, true),
+            movie_json("heat", "Heat", 1995, true),
+            movie_json("alien", "Alien", 1979, true),
+            movie_json("cats", "Cats", 2019, false),
+        ],
+    )
+    .await;
+    send(&app, Method::DELETE, "/v1/movie/cats", None).await;
+
+    assert_eq!(
+        ndjson_ids(&app, "/v1/movies").await,
+        ["alien", "heat", "up"]
+    );
+    // Filtered and paged while streaming
+    let uri = "/v1/movies?year_min=1990&offset=1&include_deleted=true";
+    assert_eq!(ndjson_ids(&app, uri).await, ["heat", "up"]);
+    // Any other order is sorted up front
+    let uri = "/v1/movies?sort=id&order=desc&limit=2";
+    assert_eq!(ndjson_ids(&app, uri).await, ["up", "heat"]);
+
+    // Without the header it's still one JSON array
+    let response = send(&app, Method::GET, "/v1/movies", None).await;
+    assert_eq!(ids(response).await, ["alien", "heat", "up"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_updates_from_one_version_let_exactly_one_through() {
+    let state = test_state();
+    let app = app(state.clone());
+    send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    let fetched = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(fetched.headers()[header::ETAG], "\"1\"");
+
+    let updates = [json!({"rating": 7.5}), json!({"rating": 9.0})].map(|patch| {
+        let app = app.clone();
+        tokio::spawn(async move {
+            let request = Request::patch("/v1/movie/heat")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_MATCH, "\"1\"")
+                .body(Body::from(patch.to_string()))
+                .unwrap();
+            app.oneshot(request).await.unwrap().status()
+        })
+    });
+    let mut statuses = Vec::new();
+    for update in updates {
+        statuses.push(update.await.unwrap());
+    }
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+    assert_eq!(state.db.get("heat").await.unwrap().unwrap().version, 2);
+
+    // Without any version at all the patch isn't applied
+    let patch = json!({"rating": 1.0});
+    let response = send(&app, Method::PATCH, "/v1/movie/heat", Some(patch)).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+}
+
+#[tokio::test]
+async fn put_replaces_the_cached_movie() {
+    let app = app(test_state());
+    send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    // Fills the cache with was_good = true
+    send(&app, Method::GET, "/v1/movie/heat", None).await;
+
+    let replacement =
+        json!({"id": "heat", "name": "Heat", "year": 1995, "was_good": false, "version": 1});
+    let response = send(&app, Method::PUT, "/v1/movie/heat", Some(replacement)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let fetched = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(json_body(fetched).await["was_good"], false);
+}
+
+// The names of every field a 422 body reports
+async fn invalid_fields(response: Response) -> Vec<String> {
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = json_body(response).await;
+    body["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn create_rejects_an_empty_id() {
+    let app = app(test_state());
+    let movie = json!({"id": " ", "name": "Heat", "year": 1995, "was_good": true});
+    let response = send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+    assert_eq!(invalid_fields(response).await, ["id"]);
+}
+
+#[tokio::test]
+async fn create_rejects_a_blank_name() {
+    let app = app(test_state());
+    let movie = json!({"id": "heat", "name": "  \t", "year": 1995, "was_good": true});
+    let response = send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+    assert_eq!(invalid_fields(response).await, ["name"]);
+}
+
+#[tokio::test]
+async fn create_rejects_years_outside_the_valid_range() {
+    let app = app(test_state());
+    for year in [1887, 2101] {
+        let movie = json!({"id": "heat", "name": "Heat", "year": year, "was_good": true});
+        let response = send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+        assert_eq!(invalid_fields(response).await, ["year"]);
+    }
+}
+
+#[tokio::test]
+async fn create_reports_every_invalid_field_at_once() {
+    let app = app(test_state());
+    let movie = json!({"id": "", "name": "", "year": 3000, "was_good": true});
+    let response = send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+    assert_eq!(invalid_fields(response).await, ["id", "name", "year"]);
+}
+
+#[tokio::test]
+async fn create_accepts_the_boundary_years() {
+    let app = app(test_state());
+    for (id, year) in [("first", 1888), ("last", 2100)] {
+        let movie = json!({"id": id, "name": "Edge", "year": year, "was_good": true});
+        let response = send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
+
+#[tokio::test]
+async fn metrics_scrape_counts_hits_and_misses() {
+    let app = app(test_state());
+    send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    // A miss that fills the cache, then a hit
+    send(&app, Method::GET, "/v1/movie/heat", None).await;
+    send(&app, Method::GET, "/v1/movie/heat", None).await;
+
+    let response = send(&app, Method::GET, "/metrics", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let scraped = text_body(response).await;
+    for line in [
+        "movies_cache_hits_total 1",
+        "movies_cache_misses_total 1",
+        "movies_total 1",
+        "http_requests_total{method=\"GET\",path=\"/v1/movie/:movie_id\",status=\"200\"} 2",
+    ] {
+        assert!(
+            scraped.lines().any(|l| l == line),
+            "no `{line}` in\n{scraped}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn metrics_stay_unversioned_and_open_when_reads_are_protected() {
+    let mut state = with_api_key("secret");
+    state.config = Arc::new(Config {
+        api_key_protect_reads: true,
+        ..Config::default()
+    });
+    let app = app(state);
+
+    let response = send(&app, Method::GET, "/metrics", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&app, Method::GET, "/v1/metrics", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn search_ands_every_filter() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [
+            movie_json("godfather", "The Godfather", 1972, true),
+            movie_json("godzilla", "Godzilla", 1998, false),
+            movie_json("heat", "Heat", 1995, true),
+        ],
+    )
+    .await;
+
+    let response = send(
+        &app,
+        Method::GET,
+        "/v1/movies/search?name=god&was_good=true",
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(ids(response).await, ["godfather"]);
+
+    // No filters at all lists everything
+    let response = send(&app, Method::GET, "/v1/movies/search", None).await;
+    assert_eq!(ids(response).await, ["godfather", "godzilla", "heat"]);
+}
+
+#[tokio::test]
+async fn bulk_insert_reports_each_movie() {
+    let app = app(test_state());
+    seed(&app, [heat()]).await;
+
+    let batch = json!([
+        movie_json("alien", "Alien", 1979, true),
+        heat(),
+        movie_json("bad", "", 1979, true),
+        // Second copy within the same batch
+        movie_json("alien", "Alien", 1979, true),
+    ]);
+    let response = send(&app, Method::POST, "/v1/movies/bulk", Some(batch)).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let results = json_body(response).await;
+    let statuses: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| (result["id"].clone(), result["status"].clone()))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            (json!("alien"), json!("Created")),
+            (json!("heat"), json!("Conflict")),
+            (json!("bad"), json!("Invalid")),
+            (json!("alien"), json!("Conflict")),
+        ]
+    );
+    assert_eq!(results[2]["errors"][0]["field"], "name");
+
+    let fetched = send(&app, Method::GET, "/v1/movie/alien", None).await;
+    assert_eq!(fetched.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn create_never_leaves_a_stale_cached_copy() {
+    let state = test_state();
+    let app = app(state.clone());
+    seed(&app, [heat()]).await;
+    send(&app, Method::GET, "/v1/movie/heat", None).await;
+
+    // POST is create-only, so re-posting with was_good flipped is refused and GET still
+    // agrees with the db
+    let flipped = movie_json("heat", "Heat", 1995, false);
+    let response = send(&app, Method::POST, "/v1/movie", Some(flipped)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let fetched = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(json_body(fetched).await["was_good"], true);
+
+    // A leftover cache entry for an id the db doesn't have is evicted by the create
+    let mut leftover: Movie = serde_json::from_value(heat()).unwrap();
+    leftover.id = "alien".to_string();
+    state.cache.set(leftover).await;
+    seed(&app, [movie_json("alien", "Alien", 1979, false)]).await;
+    let fetched = send(&app, Method::GET, "/v1/movie/alien", None).await;
+    let fetched = json_body(fetched).await;
+    assert_eq!(fetched["name"], "Alien");
+    assert_eq!(fetched["was_good"], false);
+}
+
+// Counts db reads, each one slow enough that concurrent misses all overlap it
+#[derive(Default)]
+struct CountingReads {
+    store: InMemoryStore,
+    gets: AtomicUsize,
+}
+
+#[async_trait]
+impl MovieStore for CountingReads {
+    async fn get(&self, id: &str) -> Result<Option<Movie>, StoreError> {
+        self.gets.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        self.store.get(id).await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<bool, StoreError> {
+        self.store.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie, expected_version: u64) -> Result<bool, StoreError> {
+        self.store.update(movie, expected_version).await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, StoreError> {
+        self.store.list().await
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_misses_load_from_the_db_once() {
+    let db = Arc::new(CountingReads::default());
+    let cache = MemoryCache::new(NonZeroUsize::new(100).unwrap(), Duration::from_secs(60));
+    let app = app(AppState::new(db.clone(), Arc::new(cache)));
+    // Straight into the store so the create doesn't count as a read
+    let movie: Movie = serde_json::from_value(heat()).unwrap();
+    db.store.insert(movie).await.unwrap();
+
+    let gets: Vec<_> = (0..100)
+        .map(|_| {
+            let app = app.clone();
+            tokio::spawn(async move { send(&app, Method::GET, "/v1/movie/heat", None).await })
+        })
+        .collect();
+    for get in gets {
+        assert_eq!(get.await.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(db.gets.load(Ordering::SeqCst), 1);
+}
+
+fn with_api_key(key: &str) -> AppState {
+    let mut state = test_state();
+    state.api_key = Some(Arc::from(key));
+    state
+}
+
+#[tokio::test]
+async fn writes_without_the_right_api_key_are_unauthorized() {
+    let app = app(with_api_key("secret"));
+
+    let response = send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(json_body(response).await["code"], "UNAUTHORIZED");
+
+    let mut wrong_key = request(Method::POST, "/v1/movie", Some(heat()));
+    wrong_key
+        .headers_mut()
+        .insert(&API_KEY_HEADER, HeaderValue::from_static("guess"));
+    assert_eq!(
+        oneshot(&app, wrong_key).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Reads stay open by default
+    let response = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn writes_with_the_api_key_go_through() {
+    let app = app(with_api_key("secret"));
+    let mut create = request(Method::POST, "/v1/movie", Some(heat()));
+    create
+        .headers_mut()
+        .insert(&API_KEY_HEADER, HeaderValue::from_static("secret"));
+    assert_eq!(oneshot(&app, create).await.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn writes_need_no_key_when_auth_is_disabled() {
+    let app = app(test_state());
+    let response = send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+// Two writes a minute, and whether X-Forwarded-For is believed
+fn rate_limited(trust_forwarded_for: bool) -> AppState {
+    let mut state = test_state();
+    state.rate_limiter = Some(Arc::new(RateLimiter::new(2)));
+    state.config = Arc::new(Config {
+        trust_forwarded_for,
+        ..Config::default()
+    });
+    state
+}
+
+// A create from `peer`, claiming to be `forwarded_for` if set
+fn create_from(id: &str, peer: [u8; 4], forwarded_for: Option<&'static str>) -> Request<Body> {
+    let movie = movie_json(id, id, 1995, true);
+    let mut request = request(Method::POST, "/v1/movie", Some(movie));
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from((peer, 4000))));
+    if let Some(ip) = forwarded_for {
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", HeaderValue::from_static(ip));
+    }
+    request
+}
+
+#[tokio::test]
+async fn writes_past_the_limit_get_429_with_retry_after() {
+    let app = app(rate_limited(false));
+    for id in ["a", "b"] {
+        let response = oneshot(&app, create_from(id, [10, 0, 0, 1], None)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = oneshot(&app, create_from("c", [10, 0, 0, 1], None)).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    // One token refills every 30s at 2 a minute
+    assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+
+    // Other clients have their own bucket
+    let response = oneshot(&app, create_from("c", [10, 0, 0, 2], None)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn forwarded_for_is_ignored_unless_trusted() {
+    let untrusting = app(rate_limited(false));
+    let spoofed = ["1.1.1.1", "2.2.2.2", "3.3.3.3"];
+    let mut statuses = Vec::new();
+    for (id, ip) in ["a", "b", "c"].into_iter().zip(spoofed) {
+        let response = oneshot(&untrusting, create_from(id, [10, 0, 0, 1], Some(ip))).await;
+        statuses.push(response.status());
+    }
+    assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+
+    // Behind a trusted proxy every client shares the peer address, the header tells them apart
+    let trusting = app(rate_limited(true));
+    for (id, ip) in ["a", "b", "c"].into_iter().zip(spoofed) {
+        let response = oneshot(&trusting, create_from(id, [10, 0, 0, 1], Some(ip))).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
+
+#[tokio::test]
+async fn preflight_from_an_allowed_origin_gets_204_with_cors_headers() {
+    let mut state = test_state();
+    state.config = Arc::new(Config {
+        allowed_origins: Some("https://app.example".parse().unwrap()),
+        ..Config::default()
+    });
+    let app = app(state);
+
+    let preflight = |origin: &'static str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/v1/movie")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = oneshot(&app, preflight("https://app.example")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://app.example"
+    );
+
+    let response = oneshot(&app, preflight("https://elsewhere.example")).await;
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+#[tokio::test]
+async fn bodies_over_the_limit_get_413() {
+    let mut state = test_state();
+    state.config = Arc::new(Config {
+        max_body_bytes: 64,
+        ..Config::default()
+    });
+    let app = app(state);
+
+    let mut movie = heat();
+    movie["name"] = json!("x".repeat(100));
+    let response = send(&app, Method::POST, "/v1/movie", Some(movie)).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(json_body(response).await["code"], "PAYLOAD_TOO_LARGE");
+
+    let response = send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+async fn post_raw(app: &Router, body: &'static str) -> Value {
+    let request = Request::post("/v1/movie")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let response = oneshot(app, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    json_body(response).await
+}
+
+#[tokio::test]
+async fn syntactically_invalid_json_is_a_400_saying_where() {
+    let app = app(test_state());
+    let body = post_raw(&app, r#"{"id": "heat", "name": }"#).await;
+    assert_eq!(body["code"], "BAD_REQUEST");
+    assert_eq!(
+        body["error"],
+        "invalid JSON: name: expected value at line 1 column 24"
+    );
+}
+
+#[tokio::test]
+async fn json_missing_a_required_field_is_a_400_naming_it() {
+    let app = app(test_state());
+    let body = post_raw(&app, r#"{"id": "heat", "name": "Heat", "was_good": true}"#).await;
+    assert_eq!(body["code"], "BAD_REQUEST");
+    let error = body["error"].as_str().unwrap();
+    assert!(
+        error.starts_with("invalid JSON: missing field `year`"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn count_matches_the_movies_inserted() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [
+            movie_json("alien", "Alien", 1979, true),
+            movie_json("heat", "Heat", 1995, true),
+            movie_json("up", "Up", 2009, true),
+        ],
+    )
+    .await;
+
+    let response = send(&app, Method::GET, "/v1/movies/count", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await, json!({"count": 3}));
+}
+
+#[tokio::test]
+async fn stats_aggregate_every_movie() {
+    let app = app(test_state());
+    let response = send(&app, Method::GET, "/v1/movies/stats", None).await;
+    assert_eq!(
+        json_body(response).await,
+        json!({
+            "total": 0, "good_count": 0, "bad_count": 0,
+            "earliest_year": null, "latest_year": null, "average_year": null,
+        })
+    );
+
+    seed(
+        &app,
+        [
+            movie_json("fame", "Fame", 1980, true),
+            movie_json("heat", "Heat", 1995, true),
+            movie_json("cats", "Cats", 2010, false),
+        ],
+    )
+    .await;
+    let response = send(&app, Method::GET, "/v1/movies/stats", None).await;
+    assert_eq!(
+        json_body(response).await,
+        json!({
+            "total": 3, "good_count": 2, "bad_count": 1,
+            "earliest_year": 1980, "latest_year": 2010, "average_year": 1995.0,
+        })
+    );
+}
+
+fn rated(id: &str, rating: Option<f32>) -> Value {
+    json!({"id": id, "name": id, "year": 2000, "was_good": true, "rating": rating})
+}
+
+#[tokio::test]
+async fn list_sorts_by_rating_with_unrated_last() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [
+            rated("meh", Some(5.5)),
+            rated("unrated", None),
+            rated("best", Some(9.1)),
+            rated("worst", Some(0.0)),
+        ],
+    )
+    .await;
+
+    let response = send(&app, Method::GET, "/v1/movies?sort=rating&order=desc", None).await;
+    assert_eq!(ids(response).await, ["best", "meh", "worst", "unrated"]);
+    let response = send(&app, Method::GET, "/v1/movies?sort=rating", None).await;
+    assert_eq!(ids(response).await, ["worst", "meh", "best", "unrated"]);
+}
+
+#[tokio::test]
+async fn out_of_range_rating_is_rejected() {
+    let app = app(test_state());
+    for rating in [-0.5, 10.5] {
+        let response = send(
+            &app,
+            Method::POST,
+            "/v1/movie",
+            Some(rated("x", Some(rating))),
+        )
+        .await;
+        assert_eq!(invalid_fields(response).await, ["rating"]);
+    }
+}
+
+fn get_if_none_match(uri: &str, etag: &HeaderValue) -> Request<Body> {
+    let mut request = request(Method::GET, uri, None);
+    request
+        .headers_mut()
+        .insert(header::IF_NONE_MATCH, etag.clone());
+    request
+}
+
+#[tokio::test]
+async fn matching_etag_gets_304() {
+    let app = app(test_state());
+    seed(&app, [heat()]).await;
+    let first = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    let etag = first.headers()[header::ETAG].clone();
+
+    let response = oneshot(&app, get_if_none_match("/v1/movie/heat", &etag)).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag);
+    assert!(text_body(response).await.is_empty());
+
+    // Any write changes the tag
+    let patch = json!({"was_good": false, "version": 1});
+    send(&app, Method::PATCH, "/v1/movie/heat", Some(patch)).await;
+    let response = oneshot(&app, get_if_none_match("/v1/movie/heat", &etag)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()[header::ETAG], etag);
+}
+
+#[tokio::test]
+async fn csv_export_parses_back_into_the_same_movies() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [
+            movie_json("gbu", "The Good, the Bad and the Ugly", 1966, true),
+            movie_json("quote", "Say \"Cheese\"", 2001, false),
+            movie_json("heat", "Heat", 1995, true),
+        ],
+    )
+    .await;
+
+    let response = send(&app, Method::GET, "/v1/movies/export.csv", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/csv; charset=utf-8"
+    );
+    let exported = text_body(response).await;
+    let mut reader = csv::Reader::from_reader(exported.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec!["id", "name", "year", "was_good"]
+    );
+    let rows: Vec<(String, String, u16, bool)> = reader.deserialize().map(Result::unwrap).collect();
+    assert_eq!(
+        rows,
+        [
+            (
+                "gbu".into(),
+                "The Good, the Bad and the Ugly".into(),
+                1966,
+                true
+            ),
+            ("heat".into(), "Heat".into(), 1995, true),
+            ("quote".into(), "Say \"Cheese\"".into(), 2001, false),
+        ]
+    );
+}
+
+fn with_idempotency_key(key: &'static str, body: Value) -> Request<Body> {
+    let mut request = request(Method::POST, "/v1/movie", Some(body));
+    request.headers_mut().insert(
+        &crate::idempotency::IDEMPOTENCY_KEY_HEADER,
+        HeaderValue::from_static(key),
+    );
+    request
+}
+
+#[tokio::test]
+async fn retried_post_with_the_same_key_creates_one_movie() {
+    let app = app(test_state());
+    // No id, so without the key the retry would get a second server-generated one
+    let body = json!({"name": "Heat", "year": 1995, "was_good": true});
+
+    let first = oneshot(&app, with_idempotency_key("retry-1", body.clone())).await;
+    assert_eq!(first.status(), StatusCode::CREATED);
+    let first = json_body(first).await;
+    let retry = oneshot(&app, with_idempotency_key("retry-1", body)).await;
+    assert_eq!(retry.status(), StatusCode::CREATED);
+    assert_eq!(json_body(retry).await, first);
+
+    let response = send(&app, Method::GET, "/v1/movies/count", None).await;
+    assert_eq!(json_body(response).await, json!({"count": 1}));
+}
+
+#[tokio::test]
+async fn reusing_a_key_for_another_movie_is_rejected() {
+    let app = app(test_state());
+    let response = oneshot(&app, with_idempotency_key("reused", heat())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let other = movie_json("up", "Up", 2009, true);
+    let response = oneshot(&app, with_idempotency_key("reused", other)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = send(&app, Method::GET, "/v1/movie/up", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn year_range_is_inclusive_and_open_ended() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [
+            movie_json("alien", "Alien", 1979, true),
+            movie_json("goodfellas", "Goodfellas", 1990, true),
+            movie_json("heat", "Heat", 1995, true),
+            movie_json("matrix", "The Matrix", 1999, true),
+            movie_json("gladiator", "Gladiator", 2000, true),
+        ],
+    )
+    .await;
+
+    let response = send(
+        &app,
+        Method::GET,
+        "/v1/movies?year_min=1990&year_max=1999",
+        None,
+    )
+    .await;
+    assert_eq!(ids(response).await, ["goodfellas", "heat", "matrix"]);
+    let response = send(&app, Method::GET, "/v1/movies?year_min=1999", None).await;
+    assert_eq!(ids(response).await, ["gladiator", "matrix"]);
+    let response = send(&app, Method::GET, "/v1/movies?year_max=1990", None).await;
+    assert_eq!(ids(response).await, ["alien", "goodfellas"]);
+
+    let response = send(
+        &app,
+        Method::GET,
+        "/v1/movies?year_min=2000&year_max=1990",
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn ids_are_looked_up_trimmed_and_case_insensitively() {
+    let app = app(test_state());
+    seed(
+        &app,
+        [movie_json(
+            "TT0111161",
+            "The Shawshank Redemption",
+            1994,
+            true,
+        )],
+    )
+    .await;
+
+    let response = send(&app, Method::GET, "/v1/movie/tt0111161%20%20", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["id"], "tt0111161");
+    let response = send(&app, Method::DELETE, "/v1/movie/%20TT0111161", None).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn same_name_and_year_is_flagged_as_a_likely_duplicate() {
+    let app = app(test_state());
+    let response = send(&app, Method::POST, "/v1/movie", Some(heat())).await;
+    assert!(!response.headers().contains_key(header::WARNING));
+
+    let again = movie_json("heat-1995", " HEAT ", 1995, true);
+    let response = send(&app, Method::POST, "/v1/movie", Some(again)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let warning = response.headers()[header::WARNING].to_str().unwrap();
+    assert!(
+        warning.starts_with("299 ") && warning.contains("`heat`"),
+        "{warning}"
+    );
+
+    let strict = movie_json("heat-again", "Heat", 1995, true);
+    let response = send(&app, Method::POST, "/v1/movie?strict=true", Some(strict)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(&app, Method::GET, "/v1/movie/heat-again", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn responses_are_gzipped_only_when_accepted() {
+    let app = app(test_state());
+    seed(
+        &app,
+        (0..20).map(|i| movie_json(&format!("movie-{i}"), "Heat", 1995, true)),
+    )
+    .await;
+
+    let mut gzip = request(Method::GET, "/v1/movies", None);
+    gzip.headers_mut()
+        .insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+    let response = oneshot(&app, gzip).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    let compressed = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    let response = send(&app, Method::GET, "/v1/movies", None).await;
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    let plain = text_body(response).await;
+    assert!(compressed.len() < plain.len());
+    assert_eq!(
+        serde_json::from_str::<Value>(&plain)
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .len(),
+        20
+    );
+}
+
+#[tokio::test]
+async fn handlers_slower_than_the_timeout_get_408() {
+    let db = Arc::new(CountingReads::default());
+    let cache = MemoryCache::new(NonZeroUsize::new(100).unwrap(), Duration::from_secs(60));
+    let mut state = AppState::new(db.clone(), Arc::new(cache));
+    // Well under the 50ms every read takes
+    state.config = Arc::new(Config {
+        request_timeout: Duration::from_millis(10),
+        ..Config::default()
+    });
+    let app = app(state);
+    let movie: Movie = serde_json::from_value(heat()).unwrap();
+    db.store.insert(movie).await.unwrap();
+
+    let response = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    // Anything not touching a read still fits
+    let response = send(&app, Method::GET, "/v1/movies/count", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+// Carrying the key `with_api_key("secret")` expects
+fn with_secret(method: Method, uri: &str, body: Option<Value>) -> Request<Body> {
+    let mut request = request(method, uri, body);
+    request
+        .headers_mut()
+        .insert(&API_KEY_HEADER, HeaderValue::from_static("secret"));
+    request
+}
+
+#[tokio::test]
+async fn admin_lists_and_flushes_the_cache() {
+    let app = app(with_api_key("secret"));
+    let create = with_secret(Method::POST, "/v1/movie", Some(heat()));
+    assert_eq!(oneshot(&app, create).await.status(), StatusCode::CREATED);
+    send(&app, Method::GET, "/v1/movie/heat", None).await;
+
+    let response = oneshot(&app, with_secret(Method::GET, "/admin/cache", None)).await;
+    assert_eq!(
+        json_body(response).await,
+        json!({"size": 1, "keys": ["heat"]})
+    );
+    let response = send(&app, Method::GET, "/admin/cache", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = oneshot(&app, with_secret(Method::DELETE, "/admin/cache", None)).await;
+    assert_eq!(json_body(response).await, json!({"cleared": 1}));
+    let response = oneshot(&app, with_secret(Method::GET, "/admin/cache", None)).await;
+    assert_eq!(json_body(response).await, json!({"size": 0, "keys": []}));
+}
+
+#[tokio::test]
+async fn request_ids_are_echoed_or_generated() {
+    let app = app(test_state());
+    let mut traced = request(Method::GET, "/v1/movies", None);
+    traced
+        .headers_mut()
+        .insert(&REQUEST_ID_HEADER, HeaderValue::from_static("trace-me-123"));
+    let response = oneshot(&app, traced).await;
+    assert_eq!(response.headers()[&REQUEST_ID_HEADER], "trace-me-123");
+
+    let response = send(&app, Method::GET, "/v1/movies", None).await;
+    let generated = response.headers()[&REQUEST_ID_HEADER].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(generated).is_ok(), "{generated}");
+}
+
+#[tokio::test]
+async fn bulk_created_movies_start_at_version_1() {
+    let app = app(test_state());
+    let mut claimed = heat();
+    claimed["version"] = json!(7);
+    let batch = json!([claimed, movie_json("up", "Up", 2009, true)]);
+    let response = send(&app, Method::POST, "/v1/movies/bulk", Some(batch)).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    for id in ["heat", "up"] {
+        let response = send(&app, Method::GET, &format!("/v1/movie/{id}"), None).await;
+        assert_eq!(json_body(response).await["version"], 1);
+    }
+    let patch = json!({"was_good": false, "version": 1});
+    let response = send(&app, Method::PATCH, "/v1/movie/heat", Some(patch)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn put_without_a_version_is_428() {
+    let app = app(test_state());
+    seed(&app, [heat()]).await;
+
+    let response = send(&app, Method::PUT, "/v1/movie/heat", Some(heat())).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+    let mut put = request(Method::PUT, "/v1/movie/heat", Some(heat()));
+    put.headers_mut()
+        .insert(header::IF_MATCH, HeaderValue::from_static("\"1\""));
+    let response = oneshot(&app, put).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], "\"2\"");
+}
+
+#[tokio::test]
+async fn preflight_allows_if_match() {
+    let mut state = test_state();
+    state.config = Arc::new(Config {
+        allowed_origins: Some("https://app.example".parse().unwrap()),
+        ..Config::default()
+    });
+    let app = app(state);
+
+    let preflight = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/v1/movie/heat")
+        .header(header::ORIGIN, "https://app.example")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "if-match")
+        .body(Body::empty())
+        .unwrap();
+    let response = oneshot(&app, preflight).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let allowed = response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+        .to_str()
+        .unwrap();
+    assert!(
+        allowed.split(',').any(|name| name.trim() == "if-match"),
+        "{allowed}"
+    );
+}
+
+#[tokio::test]
+async fn created_without_an_id_gets_a_uuid_that_round_trips() {
+    let app = app(test_state());
+    let body = json!({"name": "Heat", "year": 1995, "was_good": true});
+    let response = send(&app, Method::POST, "/v1/movie", Some(body)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[header::LOCATION]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let id = json_body(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(uuid::Uuid::parse_str(&id).is_ok(), "{id}");
+
+    let response = send(&app, Method::GET, &location, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["id"], id);
+}
+
+#[tokio::test]
+async fn random_picks_one_of_the_matching_movies() {
+    let app = app(test_state());
+    let response = send(&app, Method::GET, "/v1/movies/random", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    seed(
+        &app,
+        [
+            movie_json("alien", "Alien", 1979, true),
+            movie_json("heat", "Heat", 1995, true),
+            movie_json("cats", "Cats", 2019, false),
+        ],
+    )
+    .await;
+    for _ in 0..20 {
+        let response = send(&app, Method::GET, "/v1/movies/random", None).await;
+        let id = json_body(response).await["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(["alien", "heat", "cats"].contains(&id.as_str()), "{id}");
+
+        let response = send(&app, Method::GET, "/v1/movies/random?was_good=true", None).await;
+        assert_eq!(json_body(response).await["was_good"], true);
+    }
+    let response = send(&app, Method::GET, "/v1/movies/random?was_good=false", None).await;
+    assert_eq!(json_body(response).await["id"], "cats");
+}
+
+#[tokio::test]
+async fn created_movies_are_posted_to_the_webhook() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Value>(1);
+    let receiver = Router::new().route(
+        "/hook",
+        axum::routing::post(move |axum::Json(body): axum::Json<Value>| async move {
+            tx.send(body).await.unwrap();
+            StatusCode::NO_CONTENT
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+    let mut state = test_state();
+    state.webhook = Some(Arc::new(Webhook::new(url.parse().unwrap())));
+    let app = app(state);
+    seed(&app, [heat()]).await;
+
+    let delivered = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook never arrived")
+        .unwrap();
+    assert_eq!(delivered["id"], "heat");
+    assert_eq!(delivered["name"], "Heat");
+}
+
+#[tokio::test]
+async fn a_hanging_webhook_receiver_doesnt_delay_the_create() {
+    // Never accepted, so delivery waits out the whole client timeout
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+    let mut state = test_state();
+    state.webhook = Some(Arc::new(Webhook::new(url.parse().unwrap())));
+    let app = app(state);
+
+    let create = send(&app, Method::POST, "/v1/movie", Some(heat()));
+    let response = tokio::time::timeout(Duration::from_secs(1), create)
+        .await
+        .expect("create waited on the webhook");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    drop(listener);
+}
+
+#[tokio::test]
+async fn warming_fills_the_cache_up_to_its_capacity() {
+    let cache = MemoryCache::new(NonZeroUsize::new(3).unwrap(), Duration::from_secs(60));
+    let state = AppState::new(Arc::new(InMemoryStore::default()), Arc::new(cache));
+    for i in 0..5 {
+        let movie: Movie =
+            serde_json::from_value(movie_json(&format!("movie-{i}"), "Heat", 1995, true)).unwrap();
+        state.db.insert(movie).await.unwrap();
+    }
+
+    let warmed = state.warm_cache(10).await.unwrap();
+    let keys = state.cache.keys().await;
+    assert_eq!(warmed, 3);
+    assert_eq!(keys.len(), state.cache.capacity().unwrap());
+    let app = app(state.clone());
+    for id in keys {
+        send(&app, Method::GET, &format!("/v1/movie/{id}"), None).await;
+    }
+    assert_eq!(metric(&state, "movies_cache_hits_total"), 3);
+}
+
+#[tokio::test]
+async fn max_version_if_match_is_a_conflict_not_an_overflow() {
+    let app = app(test_state());
+    seed(&app, [heat()]).await;
+    let max = HeaderValue::from_str(&format!("\"{}\"", u64::MAX)).unwrap();
+
+    let mut put = request(Method::PUT, "/v1/movie/heat", Some(heat()));
+    put.headers_mut().insert(header::IF_MATCH, max.clone());
+    assert_eq!(oneshot(&app, put).await.status(), StatusCode::CONFLICT);
+
+    let patch = json!({"was_good": false});
+    let mut patch = request(Method::PATCH, "/v1/movie/heat", Some(patch));
+    patch.headers_mut().insert(header::IF_MATCH, max);
+    assert_eq!(oneshot(&app, patch).await.status(), StatusCode::CONFLICT);
+
+    let response = send(&app, Method::GET, "/v1/movie/heat", None).await;
+    assert_eq!(json_body(response).await["version"], 1);
+}
+
+#[tokio::test]
+async fn openapi_lists_the_path_id_and_if_match_for_conditional_writes() {
+    let app = app(test_state());
+    let response = send(&app, Method::GET, "/api-docs/openapi.json", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let spec = json_body(response).await;
+
+    for method in ["put", "patch"] {
+        let params = spec["paths"]["/v1/movie/{movie_id}"][method]["parameters"]
+            .as_array()
+            .unwrap_or_else(|| panic!("no parameters for {method}"));
+        let mut names: Vec<(&str, &str)> = params
+            .iter()
+            .map(|param| {
+                (
+                    param["name"].as_str().unwrap(),
+                    param["in"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [("If-Match", "header"), ("movie_id", "path")],
+            "{method}"
+        );
+    }
+}
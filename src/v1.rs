@@ -0,0 +1,75 @@
+use crate::{
+    add_movie, auth, batch_get_movies, bulk_add_movies, count_movies, csv_io, delete_movie, events,
+    get_movie, list_movies, movie_stats, patch_movie, random_movie, rate_limit, restore_movie,
+    search_movies, update_movie, AppState,
+};
+use axum::routing::{get, post, put};
+use axum::Router;
+use utoipa::OpenApi;
+
+/// Where this version is mounted, also used to build `Location` headers
+pub const PREFIX: &str = "/v1";
+
+/// OpenAPI for the routes below, paths are relative to `PREFIX`
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::get_movie,
+        crate::add_movie,
+        crate::update_movie,
+        crate::patch_movie,
+        crate::delete_movie,
+        crate::restore_movie,
+        crate::list_movies,
+        crate::count_movies,
+        crate::movie_stats,
+        crate::random_movie,
+        crate::search_movies,
+        crate::batch_get_movies,
+        crate::bulk_add_movies,
+        csv_io::export_csv,
+        csv_io::import_csv,
+    ),
+    // Only referenced from query params, which utoipa doesn't collect on its own
+    components(schemas(crate::SortField, crate::SortOrder))
+)]
+pub struct ApiDoc;
+
+/// Every v1 route, a later version gets its own module and is nested next to this one
+pub fn router(state: &AppState, protect_reads: bool) -> Router<AppState> {
+    // Writes always need the API key (when one is configured), reads only if asked to
+    let require_api_key =
+        axum::middleware::from_fn_with_state(state.clone(), auth::require_api_key);
+    let reads = Router::new()
+        .route("/movie/:movie_id", get(get_movie))
+        .route("/movies", get(list_movies))
+        .route("/movies/count", get(count_movies))
+        .route("/movies/export.csv", get(csv_io::export_csv))
+        .route("/movies/events", get(events::movie_events))
+        .route("/movies/stats", get(movie_stats))
+        .route("/movies/random", get(random_movie))
+        .route("/movies/search", get(search_movies))
+        .route("/movies/batch", post(batch_get_movies));
+    let reads = if protect_reads {
+        reads.route_layer(require_api_key.clone())
+    } else {
+        reads
+    };
+    let writes = Router::new()
+        .route(
+            "/movie/:movie_id",
+            put(update_movie).patch(patch_movie).delete(delete_movie),
+        )
+        .route("/movie", post(add_movie))
+        .route("/movie/:movie_id/restore", post(restore_movie))
+        .route("/movies/bulk", post(bulk_add_movies))
+        .route("/movies/import", post(csv_io::import_csv))
+        .route_layer(require_api_key)
+        // Outermost so unauthenticated floods are limited too
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::rate_limit,
+        ));
+
+    reads.merge(writes)
+}
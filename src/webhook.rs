@@ -0,0 +1,56 @@
+use crate::Movie;
+use reqwest::{Client, Url};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// POSTs newly created movies to `WEBHOOK_URL`
+pub struct Webhook {
+    client: Client,
+    url: Url,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .expect("the default TLS backend is available");
+        Webhook { client, url }
+    }
+
+    /// Delivers in the background so a slow or down receiver can't hold up the request that
+    /// created the movie. Failures are only logged.
+    pub fn movie_created(&self, movie: Movie) {
+        let client = self.client.clone();
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            for attempt in 1..=ATTEMPTS {
+                let result = client
+                    .post(url.clone())
+                    .json(&movie)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                match result {
+                    Ok(_) => return,
+                    Err(err) if attempt < ATTEMPTS => {
+                        tracing::warn!(
+                            "webhook delivery for movie `{}` failed (attempt {attempt}): {err}",
+                            movie.id
+                        );
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                    Err(err) => tracing::error!(
+                        "giving up on webhook delivery for movie `{}`: {err}",
+                        movie.id
+                    ),
+                }
+            }
+        });
+    }
+}